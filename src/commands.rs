@@ -1,6 +1,9 @@
 pub mod aws;
 pub mod config;
 pub mod console;
+pub mod context;
 pub mod kubectl;
+pub mod logout;
 pub mod process;
+pub mod validate;
 pub mod view;
@@ -1,67 +1,1092 @@
 use crate::flightctl::kubeclient;
 use crate::flightctl::kubeenv;
-use crate::flightctl::{ApplicationConfig, Config, Console, Release};
+use crate::flightctl::prompt;
+use crate::flightctl::runner::Runner;
+use crate::flightctl::{Application, ApplicationConfig, Config, Console, Release};
+use k8s_openapi::api::core::v1 as k8s;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::rc::Rc;
+use std::str::FromStr;
+use structopt::StructOpt;
 
-pub fn print(config: &Config, release: &Release) -> anyhow::Result<()> {
-    let application = config.find_application(&release)?;
+pub mod diff;
+pub mod edit;
+pub mod get;
+pub mod set;
 
+#[derive(Debug, StructOpt)]
+pub struct Options {
+    /// Output format: text, json, yaml, dotenv or shell [default: text]
+    #[structopt(short, long, alias = "format")]
+    pub output: Option<Output>,
+
+    /// Show decoded secret values, or only the value of KEY if given
+    #[structopt(long, value_name = "KEY")]
+    pub reveal: Option<Option<String>>,
+
+    /// Show every container, including init containers and sidecars
+    #[structopt(long, conflicts_with_all = &["container", "init-containers"])]
+    pub all_containers: bool,
+
+    /// Show only init containers
+    #[structopt(long, conflicts_with = "container")]
+    pub init_containers: bool,
+}
+
+impl Options {
+    fn scope(&self) -> Scope {
+        if self.all_containers {
+            Scope::All
+        } else if self.init_containers {
+            Scope::Init
+        } else {
+            Scope::Named
+        }
+    }
+
+    fn shows_secret(&self, name: &str) -> bool {
+        match &self.reveal {
+            Some(Some(key)) => key == name,
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /// Fails if any of these options were given along with a subcommand,
+    /// which has no use for them.
+    pub fn check_unused(&self, subcommand: &str) -> anyhow::Result<()> {
+        let given: Vec<&str> = [
+            ("--output", self.output.is_some()),
+            ("--reveal", self.reveal.is_some()),
+            ("--all-containers", self.all_containers),
+            ("--init-containers", self.init_containers),
+        ]
+        .into_iter()
+        .filter(|(_, given)| *given)
+        .map(|(name, _)| name)
+        .collect();
+        if given.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow::Error::msg(format!(
+                "{} can't be used with config {}",
+                given.join(", "),
+                subcommand
+            )))
+        }
+    }
+}
+
+/// Which container's environment to inspect.
+#[derive(Debug, StructOpt)]
+pub struct Target {
+    /// Container to inspect, as NAME or DEPLOYMENT/NAME [default: the console container]
+    #[structopt(short, long)]
+    pub container: Option<String>,
+}
+
+impl Target {
+    pub fn merge(&self, other: &Target) -> Target {
+        Target {
+            container: other.container.clone().or_else(|| self.container.clone()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Output {
+    Text,
+    Json,
+    Yaml,
+    Dotenv,
+    Shell,
+}
+
+impl FromStr for Output {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Output> {
+        match value {
+            "text" => Ok(Output::Text),
+            "json" => Ok(Output::Json),
+            "yaml" => Ok(Output::Yaml),
+            "dotenv" => Ok(Output::Dotenv),
+            "shell" => Ok(Output::Shell),
+            _ => Err(anyhow::Error::msg(format!(
+                "Unknown output format: {} (expected text, json, yaml, dotenv or shell)",
+                value
+            ))),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct OutputVar<'a> {
+    name: &'a str,
+    value: Option<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    template: Option<&'a str>,
+    source: OutputSource<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    missing: Option<kubeenv::Missing>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    redacted: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    shadowed: bool,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+enum OutputSource<'a> {
+    Pod,
+    #[serde(rename = "configmap")]
+    ConfigMap {
+        name: &'a str,
+        key: &'a str,
+    },
+    Secret {
+        name: &'a str,
+        key: &'a str,
+    },
+    FieldRef {
+        path: &'a str,
+    },
+    ResourceFieldRef {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        container: Option<&'a str>,
+        resource: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        divisor: Option<&'a str>,
+    },
+}
+
+/// Which of a pod's containers to resolve.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    /// The container given with `--container`, or the default one.
+    Named,
+    /// Every container, including init containers.
+    All,
+    /// Only init containers.
+    Init,
+}
+
+/// The resolved environment of one container, labelled as NAME for the
+/// console pod or DEPLOYMENT/NAME for a deployment's pod template.
+pub struct ContainerEnv {
+    pub label: String,
+    pub init: bool,
+    pub env: kubeenv::ResolvedEnv,
+}
+
+#[derive(Serialize)]
+struct OutputContainer<'a> {
+    container: &'a str,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    init: bool,
+    variables: Vec<OutputVar<'a>>,
+}
+
+/// A container to resolve along with the pod (or pod template) it's in.
+struct Candidate {
+    label: String,
+    init: bool,
+    pod: k8s::Pod,
+    container: k8s::Container,
+}
+
+pub fn print(
+    runner: &dyn Runner,
+    config: &Config,
+    release: &Release,
+    target: &Target,
+    options: &Options,
+) -> anyhow::Result<()> {
+    let scope = options.scope();
+    let containers = resolve_containers(runner, config, release, target, scope)?;
+    if containers.is_empty() {
+        log::info!("No matching containers for release {}", release.name);
+        return Ok(());
+    }
+    match &options.reveal {
+        Some(Some(key)) => {
+            check_secret(&containers, key)?;
+            confirm_reveal(release, &format!("secret {}", key))?;
+        }
+        Some(None) => confirm_reveal(release, "secrets")?,
+        None => {}
+    }
+
+    // A single container keeps the flat output; several are grouped.
+    let grouped = scope != Scope::Named;
+    let output = options.output.as_ref().unwrap_or(&Output::Text);
+    match output {
+        Output::Text => {
+            for (index, container) in containers.iter().enumerate() {
+                if grouped {
+                    if index > 0 {
+                        println!();
+                    }
+                    println!("[{}]", heading(container));
+                }
+                print_text(&container.env.vars, options);
+            }
+        }
+        Output::Json if grouped => {
+            let output = output_containers(&containers, options);
+            println!("{}", serde_json::to_string_pretty(&output)?);
+        }
+        Output::Json => {
+            let output = output_vars(&containers[0].env.vars, options);
+            println!("{}", serde_json::to_string_pretty(&output)?);
+        }
+        Output::Yaml if grouped => {
+            let output = output_containers(&containers, options);
+            print!("{}", serde_yaml::to_string(&output)?);
+        }
+        Output::Yaml => {
+            let output = output_vars(&containers[0].env.vars, options);
+            print!("{}", serde_yaml::to_string(&output)?);
+        }
+        Output::Dotenv | Output::Shell => {
+            let (prefix, quote): (&str, fn(&str) -> String) = match output {
+                Output::Shell => ("export ", quote_shell),
+                _ => ("", quote_dotenv),
+            };
+            for container in &containers {
+                if grouped {
+                    println!("# {}", heading(container));
+                }
+                print_exports(&output_vars(&container.env.vars, options), prefix, quote);
+            }
+        }
+    }
+    check_errors(&containers)
+}
+
+fn heading(container: &ContainerEnv) -> String {
+    if container.init {
+        format!("{} (init container)", container.label)
+    } else {
+        container.label.clone()
+    }
+}
+
+fn output_containers<'a>(
+    containers: &'a [ContainerEnv],
+    options: &Options,
+) -> Vec<OutputContainer<'a>> {
+    containers
+        .iter()
+        .map(|container| OutputContainer {
+            container: &container.label,
+            init: container.init,
+            variables: output_vars(&container.env.vars, options),
+        })
+        .collect()
+}
+
+/// Resolves the environment of the targeted container: the console
+/// container by default, or the pod template of the application's
+/// deployments for applications without a console.
+pub fn resolve(
+    runner: &dyn Runner,
+    config: &Config,
+    release: &Release,
+    target: &Target,
+) -> anyhow::Result<kubeenv::ResolvedEnv> {
+    let mut containers = resolve_containers(runner, config, release, target, Scope::Named)?;
+    Ok(containers.remove(0).env)
+}
+
+/// Resolves every container in scope, sharing fetched ConfigMaps and
+/// Secrets between them.
+pub fn resolve_containers(
+    runner: &dyn Runner,
+    config: &Config,
+    release: &Release,
+    target: &Target,
+    scope: Scope,
+) -> anyhow::Result<Vec<ContainerEnv>> {
+    let application = config.find_application(release)?;
+    let client = kubeclient::new(runner, config, release);
+    resolve_with(&client, application, target, scope)
+}
+
+fn resolve_with(
+    client: &kubeclient::KubeClient,
+    application: &Application,
+    target: &Target,
+    scope: Scope,
+) -> anyhow::Result<Vec<ContainerEnv>> {
     match &application.config {
-        ApplicationConfig::Kubectl { console, selector } => {
-            let client = kubeclient::new(&release.context);
+        ApplicationConfig::Kubectl {
+            console, selector, ..
+        } => {
             let base_selector = kubeclient::Selector::new(selector.clone());
+            let requested = target.container.as_deref();
 
-            match console {
+            let candidates = match console {
                 Some(Console::Exec {
                     container,
                     selector,
                     ..
-                }) => {
+                }) if !requested.unwrap_or_default().contains('/') => {
                     let console_selector =
                         base_selector.extend(&kubeclient::Selector::new(selector.clone()));
                     let pod = client.get_available_pod(console_selector)?;
-                    let container = pod
-                        .spec
-                        .map(|spec| spec.containers.into_iter().find(|c| &c.name == container))
-                        .flatten()
-                        .ok_or(anyhow::anyhow!("Couldn't find container {}", container))?;
-                    let mut resolver = kubeenv::Resolver::new(&client);
-                    let config = resolver.resolve(container);
-                    for var in config {
-                        match var.value {
-                            kubeenv::ResolvedValue::Pod { value } => {
-                                println!("{}: {} (from pod)", &var.name, &show_value(value))
-                            }
-                            kubeenv::ResolvedValue::ConfigMapKeyRef {
-                                config_map,
-                                key,
-                                value,
-                            } => println!(
-                                "{}: {} (from configmap/{}.{})",
-                                &var.name,
-                                &show_value(value.map(|s| s.to_string())),
-                                config_map,
-                                key
-                            ),
-                            kubeenv::ResolvedValue::SecretKeyRef { secret, key } => println!(
-                                "{}: ******************** (from secret/{}.{})",
-                                &var.name, secret, key
-                            ),
-                            kubeenv::ResolvedValue::FieldRef { path } => {
-                                println!("{}: (reference to {})", &var.name, &path)
-                            }
-                        }
+                    let name = requested.unwrap_or(container);
+                    let candidates: Vec<Candidate> = pod_containers(&pod, scope, Some(name))
+                        .into_iter()
+                        .map(|(init, container)| Candidate {
+                            label: container.name.clone(),
+                            init,
+                            pod: pod.clone(),
+                            container,
+                        })
+                        .collect();
+                    if scope == Scope::Named && candidates.is_empty() {
+                        return Err(anyhow::anyhow!("Couldn't find container {}", name));
                     }
-                    Ok(())
+                    candidates
+                }
+                _ => template_containers(client, base_selector, application, requested, scope)?,
+            };
+
+            let mut resolver = kubeenv::Resolver::new(client);
+            resolver.prefetch(candidates.iter().map(|candidate| &candidate.container));
+            Ok(candidates
+                .into_iter()
+                .map(|candidate| ContainerEnv {
+                    label: candidate.label,
+                    init: candidate.init,
+                    env: resolver.resolve(&candidate.pod, candidate.container),
+                })
+                .collect())
+        }
+    }
+}
+
+/// The pod's containers in scope, each flagged if it's an init container.
+/// A named container may be either kind; without a name, only regular
+/// containers are candidates.
+fn pod_containers(pod: &k8s::Pod, scope: Scope, name: Option<&str>) -> Vec<(bool, k8s::Container)> {
+    let spec = match &pod.spec {
+        Some(spec) => spec,
+        None => return vec![],
+    };
+    let containers = spec.containers.iter().map(|container| (false, container));
+    let init_containers = spec
+        .init_containers
+        .iter()
+        .flatten()
+        .map(|container| (true, container));
+
+    let selected: Vec<(bool, &k8s::Container)> = match (scope, name) {
+        (Scope::Named, Some(name)) => containers
+            .chain(init_containers)
+            .filter(|(_, container)| container.name == name)
+            .collect(),
+        (Scope::Named, None) => containers.collect(),
+        (Scope::All, _) => containers.chain(init_containers).collect(),
+        (Scope::Init, _) => init_containers.collect(),
+    };
+    selected
+        .into_iter()
+        .map(|(init, container)| (init, container.clone()))
+        .collect()
+}
+
+/// Finds containers in the pod templates of the application's deployments.
+/// A single container can be given as NAME or DEPLOYMENT/NAME, and may be
+/// left out when there's only one to choose from.
+fn template_containers(
+    client: &kubeclient::KubeClient,
+    selector: kubeclient::Selector,
+    application: &Application,
+    requested: Option<&str>,
+    scope: Scope,
+) -> anyhow::Result<Vec<Candidate>> {
+    let (deployment_name, container_name) = match requested.map(|name| name.split_once('/')) {
+        Some(Some((deployment, container))) => (Some(deployment), Some(container)),
+        Some(None) => (None, requested),
+        None => (None, None),
+    };
+
+    let mut candidates = Vec::new();
+    for deployment in client.get_deployments(selector)? {
+        let name = deployment.metadata.name.unwrap_or_default();
+        if deployment_name.is_some_and(|wanted| wanted != name) {
+            continue;
+        }
+        let template = match deployment.spec {
+            Some(spec) => spec.template,
+            None => continue,
+        };
+        let pod = k8s::Pod {
+            metadata: template.metadata.unwrap_or_default(),
+            spec: template.spec,
+            status: None,
+        };
+        for (init, container) in pod_containers(&pod, scope, container_name) {
+            candidates.push(Candidate {
+                label: format!("{}/{}", name, container.name),
+                init,
+                pod: pod.clone(),
+                container,
+            });
+        }
+    }
+
+    if scope != Scope::Named {
+        return Ok(candidates);
+    }
+
+    match candidates.len() {
+        0 => Err(anyhow::Error::msg(match requested {
+            Some(requested) => format!(
+                "No deployment for application {} has container {}",
+                application.name, requested
+            ),
+            None => format!(
+                "Application {} has no console and no deployments",
+                application.name
+            ),
+        })),
+        1 => Ok(candidates),
+        _ => Err(anyhow::Error::msg(format!(
+            "Several containers match for application {}: {}; choose one with --container DEPLOYMENT/NAME",
+            application.name,
+            candidates
+                .iter()
+                .map(|candidate| candidate.label.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        ))),
+    }
+}
+
+/// Fails unless some container has a variable named KEY that refers to a
+/// secret, so a mistyped `--reveal KEY` doesn't go unnoticed.
+fn check_secret(containers: &[ContainerEnv], key: &str) -> anyhow::Result<()> {
+    let vars: Vec<&kubeenv::ResolvedVar> = containers
+        .iter()
+        .flat_map(|container| &container.env.vars)
+        .filter(|var| var.name.as_str() == key)
+        .collect();
+    if vars.is_empty() {
+        Err(anyhow::Error::msg(format!("{} isn't set", key)))
+    } else if !vars.iter().any(|var| is_secret(&var.value)) {
+        Err(anyhow::Error::msg(format!(
+            "{} doesn't refer to a secret",
+            key
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+fn is_secret(value: &kubeenv::ResolvedValue) -> bool {
+    match value {
+        kubeenv::ResolvedValue::SecretKeyRef { .. } => true,
+        kubeenv::ResolvedValue::Pod {
+            expanded: Some(expansion),
+            ..
+        } => expansion.has_secret(),
+        _ => false,
+    }
+}
+
+/// Asks before revealing secrets for sensitive releases, and logs every
+/// reveal that goes ahead.
+pub fn confirm_reveal(release: &Release, what: &str) -> anyhow::Result<()> {
+    if release.sensitive
+        && !prompt::confirm(&format!(
+            "Release {} is sensitive. Reveal {}?",
+            release.name, what
+        ))?
+    {
+        return Err(anyhow::Error::msg("Reveal cancelled"));
+    }
+
+    log::warn!("Revealing {} for release {}", what, release.name);
+    Ok(())
+}
+
+fn print_text(vars: &[kubeenv::ResolvedVar], options: &Options) {
+    for var in vars {
+        let line = match &var.value {
+            kubeenv::ResolvedValue::Pod {
+                value: Some(template),
+                expanded: Some(expansion),
+            } => format!(
+                "{}: {} (from pod: {})",
+                &var.name,
+                if expansion.has_secret() && !options.shows_secret(&var.name) {
+                    expansion.masked()
+                } else {
+                    expansion.value()
+                },
+                template
+            ),
+            kubeenv::ResolvedValue::Pod { value, .. } => {
+                format!("{}: {} (from pod)", &var.name, &show_value(value.clone()))
+            }
+            kubeenv::ResolvedValue::ConfigMapKeyRef {
+                config_map,
+                key,
+                value,
+            } => format!(
+                "{}: {} (from configmap/{}.{})",
+                &var.name,
+                &show_reference(value, "configmap", false),
+                config_map,
+                key
+            ),
+            kubeenv::ResolvedValue::SecretKeyRef { secret, key, value } => format!(
+                "{}: {} (from secret/{}.{})",
+                &var.name,
+                &show_reference(value, "secret", !options.shows_secret(&var.name)),
+                secret,
+                key
+            ),
+            kubeenv::ResolvedValue::FieldRef { path, value } => match value {
+                Some(value) => format!("{}: {} (from field {})", &var.name, value, &path),
+                None => format!("{}: (reference to {})", &var.name, &path),
+            },
+            kubeenv::ResolvedValue::ResourceFieldRef {
+                resource, value, ..
+            } => format!(
+                "{}: {} (from resource {})",
+                &var.name,
+                &show_value(value.clone()),
+                &resource
+            ),
+        };
+
+        if var.shadowed {
+            println!("{} [shadowed]", line);
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+/// Reports sources the containers can't start without. The variables are
+/// printed first so the rest of the configuration can still be inspected.
+fn check_errors(containers: &[ContainerEnv]) -> anyhow::Result<()> {
+    let mut count = 0;
+    for container in containers {
+        for error in &container.env.errors {
+            if containers.len() > 1 {
+                log::error!("{}: {}", container.label, error);
+            } else {
+                log::error!("{}", error);
+            }
+            count += 1;
+        }
+    }
+
+    if count == 0 {
+        Ok(())
+    } else {
+        Err(anyhow::Error::msg(format!(
+            "{} required configuration source(s) couldn't be resolved",
+            count
+        )))
+    }
+}
+
+fn output_vars<'a>(vars: &'a [kubeenv::ResolvedVar], options: &Options) -> Vec<OutputVar<'a>> {
+    vars.iter()
+        .map(|var| {
+            let shows_secret = options.shows_secret(&var.name);
+            let mut template = None;
+            let mut redacted = false;
+            let (value, source, missing) = match &var.value {
+                kubeenv::ResolvedValue::Pod {
+                    value,
+                    expanded: Some(expansion),
+                } => {
+                    template = value.as_deref();
+                    redacted = expansion.has_secret() && !shows_secret;
+                    (
+                        Some(Cow::Owned(expansion.value())).filter(|_| !redacted),
+                        OutputSource::Pod,
+                        None,
+                    )
+                }
+                kubeenv::ResolvedValue::Pod { value, .. } => {
+                    (value.as_deref().map(Cow::from), OutputSource::Pod, None)
+                }
+                kubeenv::ResolvedValue::ConfigMapKeyRef {
+                    config_map,
+                    key,
+                    value,
+                } => (
+                    value.as_ref().ok().map(|value| Cow::from(value.as_str())),
+                    OutputSource::ConfigMap {
+                        name: config_map,
+                        key,
+                    },
+                    value.as_ref().err().copied(),
+                ),
+                kubeenv::ResolvedValue::SecretKeyRef { secret, key, value } => {
+                    redacted = value.is_ok() && !shows_secret;
+                    (
+                        value
+                            .as_ref()
+                            .ok()
+                            .filter(|_| shows_secret)
+                            .map(|value| Cow::from(value.as_str())),
+                        OutputSource::Secret { name: secret, key },
+                        value.as_ref().err().copied(),
+                    )
                 }
-                None => Err(anyhow::Error::msg(format!(
-                    "No console configured for application: {}",
-                    application.name
-                ))),
+                kubeenv::ResolvedValue::FieldRef { path, value } => (
+                    value.as_deref().map(Cow::from),
+                    OutputSource::FieldRef { path },
+                    None,
+                ),
+                kubeenv::ResolvedValue::ResourceFieldRef {
+                    container,
+                    resource,
+                    divisor,
+                    value,
+                } => (
+                    value.as_deref().map(Cow::from),
+                    OutputSource::ResourceFieldRef {
+                        container: container.as_deref(),
+                        resource,
+                        divisor: divisor.as_deref(),
+                    },
+                    None,
+                ),
+            };
+            OutputVar {
+                name: &var.name,
+                value,
+                template,
+                source,
+                missing,
+                redacted,
+                shadowed: var.shadowed,
+            }
+        })
+        .collect()
+}
+
+/// Prints one assignment per variable. Anything without a value we can
+/// reproduce locally, such as an excluded secret, is left as a comment.
+fn print_exports(vars: &[OutputVar], prefix: &str, quote: fn(&str) -> String) {
+    for var in vars {
+        if var.shadowed {
+            continue;
+        }
+
+        if !is_portable_name(var.name) {
+            println!("# {}: not a valid variable name", var.name);
+            continue;
+        }
+
+        match (&var.source, var.value.as_deref()) {
+            (_, Some(value)) => println!("{}{}={}", prefix, var.name, quote(value)),
+            (OutputSource::Pod, None) if var.redacted => {
+                println!("# {}: omitted (refers to a secret)", var.name)
+            }
+            (OutputSource::Pod, None) => println!("{}{}={}", prefix, var.name, quote("")),
+            (OutputSource::ConfigMap { name, key }, None) => {
+                println!("# {}: missing (from configmap/{}.{})", var.name, name, key)
+            }
+            (OutputSource::Secret { name, key }, None) if var.missing.is_some() => {
+                println!("# {}: missing (from secret/{}.{})", var.name, name, key)
+            }
+            (OutputSource::Secret { name, key }, None) => {
+                println!("# {}: omitted (from secret/{}.{})", var.name, name, key)
+            }
+            (OutputSource::FieldRef { path }, None) => {
+                println!("# {}: omitted (reference to {})", var.name, path)
+            }
+            (OutputSource::ResourceFieldRef { resource, .. }, None) => {
+                println!("# {}: omitted (reference to {})", var.name, resource)
             }
         }
     }
 }
 
+fn is_portable_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn quote_dotenv(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '$' => quoted.push_str("\\$"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn quote_shell(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
 fn show_value(value: Option<String>) -> String {
     value.unwrap_or(String::from("(unset)"))
 }
+
+fn show_reference(value: &Result<Rc<String>, kubeenv::Missing>, kind: &str, mask: bool) -> String {
+    match value {
+        Ok(_) if mask => String::from("********************"),
+        Ok(value) => value.to_string(),
+        Err(kubeenv::Missing::Source) => format!("(missing {})", kind),
+        Err(kubeenv::Missing::Key) => String::from("(missing key)"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flightctl::testing::{self, FakeApi, FakeRunner};
+    use serde_json::json;
+
+    const PODS: &str = "/api/v1/namespaces/default/pods";
+    const DEPLOYMENTS: &str = "/apis/apps/v1/namespaces/default/deployments";
+    const CONFIG_MAP: &str = "/api/v1/namespaces/default/configmaps/app-config";
+    const SECRET: &str = "/api/v1/namespaces/default/secrets/app-secrets";
+
+    fn pod_spec() -> serde_json::Value {
+        json!({
+            "containers": [
+                {
+                    "name": "main",
+                    "envFrom": [{ "configMapRef": { "name": "app-config" } }],
+                    "env": [
+                        { "name": "RAILS_ENV", "value": "staging" },
+                        {
+                            "name": "DATABASE_PASSWORD",
+                            "valueFrom": {
+                                "secretKeyRef": { "name": "app-secrets", "key": "password" }
+                            },
+                        },
+                        {
+                            "name": "DATABASE_URL",
+                            "value": "postgres://app:$(DATABASE_PASSWORD)@db/app",
+                        },
+                        {
+                            "name": "LOG_LEVEL",
+                            "valueFrom": {
+                                "configMapKeyRef": { "name": "app-config", "key": "log-level" }
+                            },
+                        },
+                    ],
+                },
+                {
+                    "name": "proxy",
+                    "env": [{ "name": "UPSTREAM", "value": "localhost:3000" }],
+                },
+            ],
+            "initContainers": [
+                {
+                    "name": "migrate",
+                    "envFrom": [{ "secretRef": { "name": "app-secrets" } }],
+                },
+            ],
+        })
+    }
+
+    fn api() -> FakeApi {
+        FakeApi::new()
+            .serve(
+                PODS,
+                json!({
+                    "apiVersion": "v1",
+                    "kind": "PodList",
+                    "metadata": {},
+                    "items": [{ "metadata": { "name": "web-console-1" }, "spec": pod_spec() }],
+                }),
+            )
+            .serve(
+                DEPLOYMENTS,
+                json!({
+                    "apiVersion": "apps/v1",
+                    "kind": "DeploymentList",
+                    "metadata": {},
+                    "items": [
+                        {
+                            "metadata": { "name": "web" },
+                            "spec": {
+                                "selector": {},
+                                "template": { "metadata": {}, "spec": pod_spec() },
+                            },
+                        },
+                    ],
+                }),
+            )
+            .serve(
+                CONFIG_MAP,
+                json!({
+                    "apiVersion": "v1",
+                    "kind": "ConfigMap",
+                    "metadata": { "name": "app-config" },
+                    "data": { "HOST": "example.com", "log-level": "debug" },
+                }),
+            )
+            .serve(
+                SECRET,
+                json!({
+                    "apiVersion": "v1",
+                    "kind": "Secret",
+                    "metadata": { "name": "app-secrets" },
+                    "data": { "password": "aHVudGVyMg==" },
+                }),
+            )
+    }
+
+    fn target(container: Option<&str>) -> Target {
+        Target {
+            container: container.map(String::from),
+        }
+    }
+
+    fn values(env: &kubeenv::ResolvedEnv) -> Vec<(String, Option<String>)> {
+        env.vars
+            .iter()
+            .filter(|var| !var.shadowed)
+            .map(|var| {
+                let value = var.value.segments().map(|segments| {
+                    segments
+                        .into_iter()
+                        .map(|segment| match segment {
+                            kubeenv::Segment::Text(text) | kubeenv::Segment::Secret(text) => text,
+                        })
+                        .collect()
+                });
+                (var.name.to_string(), value)
+            })
+            .collect()
+    }
+
+    fn pair(name: &str, value: &str) -> (String, Option<String>) {
+        (String::from(name), Some(String::from(value)))
+    }
+
+    #[test]
+    fn resolves_console_container() {
+        let config = testing::config();
+        let runner = FakeRunner::new();
+        let api = api();
+
+        let containers = resolve_with(
+            &api.client(&runner),
+            &config.applications[0],
+            &target(None),
+            Scope::Named,
+        )
+        .unwrap();
+
+        assert_eq!(containers.len(), 1);
+        assert_eq!(containers[0].label, "main");
+        assert!(containers[0].env.errors.is_empty());
+        assert_eq!(
+            values(&containers[0].env),
+            [
+                pair("DATABASE_PASSWORD", "hunter2"),
+                pair("DATABASE_URL", "postgres://app:hunter2@db/app"),
+                pair("HOST", "example.com"),
+                pair("LOG_LEVEL", "debug"),
+                pair("RAILS_ENV", "staging"),
+                pair("log-level", "debug"),
+            ]
+        );
+        assert_eq!(
+            api.requests(),
+            [
+                format!(
+                    "{}?&fieldSelector=status.phase%3DRunning&labelSelector=app%3Dweb%2Crole%3Dconsole",
+                    PODS
+                ),
+                String::from(CONFIG_MAP),
+                String::from(SECRET),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_all_containers_sharing_sources() {
+        let config = testing::config();
+        let runner = FakeRunner::new();
+        let api = api();
+
+        let containers = resolve_with(
+            &api.client(&runner),
+            &config.applications[0],
+            &target(None),
+            Scope::All,
+        )
+        .unwrap();
+
+        let labels: Vec<(&str, bool)> = containers
+            .iter()
+            .map(|container| (container.label.as_str(), container.init))
+            .collect();
+        assert_eq!(
+            labels,
+            [("main", false), ("proxy", false), ("migrate", true)]
+        );
+        assert_eq!(values(&containers[2].env), [pair("password", "hunter2")]);
+        assert_eq!(api.requests().len(), 3);
+    }
+
+    #[test]
+    fn resolves_deployment_template() {
+        let config = testing::config();
+        let runner = FakeRunner::new();
+        let api = api();
+
+        let containers = resolve_with(
+            &api.client(&runner),
+            &config.applications[0],
+            &target(Some("web/proxy")),
+            Scope::Named,
+        )
+        .unwrap();
+
+        assert_eq!(containers.len(), 1);
+        assert_eq!(containers[0].label, "web/proxy");
+        assert_eq!(
+            values(&containers[0].env),
+            [pair("UPSTREAM", "localhost:3000")]
+        );
+        assert_eq!(
+            api.requests(),
+            [format!("{}?&labelSelector=app%3Dweb", DEPLOYMENTS)]
+        );
+    }
+
+    #[test]
+    fn requires_a_choice_between_template_containers() {
+        let mut config = testing::config();
+        match &mut config.applications[0].config {
+            ApplicationConfig::Kubectl { console, .. } => *console = None,
+        }
+        let runner = FakeRunner::new();
+
+        let err = resolve_with(
+            &api().client(&runner),
+            &config.applications[0],
+            &target(None),
+            Scope::Named,
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            err.to_string(),
+            "Several containers match for application app: web/main, web/proxy; choose one with --container DEPLOYMENT/NAME"
+        );
+    }
+
+    #[test]
+    fn reports_missing_sources() {
+        let config = testing::config();
+        let runner = FakeRunner::new();
+        let api = FakeApi::new().serve(
+            PODS,
+            json!({
+                "apiVersion": "v1",
+                "kind": "PodList",
+                "metadata": {},
+                "items": [{ "metadata": { "name": "web-console-1" }, "spec": pod_spec() }],
+            }),
+        );
+
+        let containers = resolve_with(
+            &api.client(&runner),
+            &config.applications[0],
+            &target(None),
+            Scope::Named,
+        )
+        .unwrap();
+
+        let errors: Vec<String> = containers[0]
+            .env
+            .errors
+            .iter()
+            .map(|error| error.to_string())
+            .collect();
+        for expected in [
+            r#"Couldn't fetch configmap/app-config: configmaps "app-config" not found"#,
+            r#"Couldn't fetch secret/app-secrets: secrets "app-secrets" not found"#,
+        ] {
+            assert!(errors.iter().any(|error| error == expected), "{:?}", errors);
+        }
+        assert!(values(&containers[0].env).contains(&(String::from("LOG_LEVEL"), None)));
+    }
+
+    #[test]
+    fn reveals_only_secret_variables() {
+        let config = testing::config();
+        let runner = FakeRunner::new();
+        let api = api();
+        let containers = resolve_with(
+            &api.client(&runner),
+            &config.applications[0],
+            &target(None),
+            Scope::Named,
+        )
+        .unwrap();
+
+        assert!(check_secret(&containers, "DATABASE_PASSWORD").is_ok());
+        assert!(check_secret(&containers, "DATABASE_URL").is_ok());
+        assert_eq!(
+            check_secret(&containers, "RAILS_ENV")
+                .unwrap_err()
+                .to_string(),
+            "RAILS_ENV doesn't refer to a secret"
+        );
+        assert_eq!(
+            check_secret(&containers, "DATABASE_PASWORD")
+                .unwrap_err()
+                .to_string(),
+            "DATABASE_PASWORD isn't set"
+        );
+    }
+
+    #[test]
+    fn rejects_options_for_a_subcommand() {
+        let mut options = Options {
+            output: None,
+            reveal: None,
+            all_containers: false,
+            init_containers: false,
+        };
+        assert!(options.check_unused("get").is_ok());
+
+        options.output = Some(Output::Json);
+        options.reveal = Some(Some(String::from("DATABASE_PASSWORD")));
+        assert_eq!(
+            options.check_unused("get").unwrap_err().to_string(),
+            "--output, --reveal can't be used with config get"
+        );
+    }
+
+    #[test]
+    fn quotes_values_for_dotenv_and_shell() {
+        let value = "it's \"$HOME\"\\n\nnext";
+
+        assert_eq!(quote_dotenv(value), r#""it's \"\$HOME\"\\n\nnext""#);
+        assert_eq!(quote_shell(value), "'it'\\''s \"$HOME\"\\n\nnext'");
+        assert_eq!(quote_dotenv(""), r#""""#);
+        assert_eq!(quote_shell(""), "''");
+    }
+}
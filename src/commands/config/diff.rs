@@ -0,0 +1,196 @@
+use super::Target;
+use crate::flightctl::exit::Outcome;
+use crate::flightctl::kubeenv::{ResolvedEnv, ResolvedValue, ResolvedVar, Segment};
+use crate::flightctl::runner::Runner;
+use crate::flightctl::{Config, Release};
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::BuildHasher;
+
+/// Exit status when the releases' configuration differs.
+pub const DIFFERENT: i32 = 1;
+
+/// What a variable looks like for comparison purposes. Secret values are
+/// kept only as a fingerprint so they can be compared without printing them.
+#[derive(Debug, PartialEq)]
+enum Comparable {
+    Value(String),
+    Secret(u64),
+    Reference(String),
+    Missing,
+}
+
+pub fn run(
+    runner: &dyn Runner,
+    config: &Config,
+    target: &Target,
+    from: &Release,
+    to: &Release,
+) -> anyhow::Result<()> {
+    let salt = RandomState::new();
+    let from_vars = comparable_vars(super::resolve(runner, config, from, target)?, from, &salt);
+    let to_vars = comparable_vars(super::resolve(runner, config, to, target)?, to, &salt);
+    let mut differences = 0;
+
+    for (name, from_value) in &from_vars {
+        match to_vars.get(name) {
+            None => {
+                println!("- {}: {}", name, show(from_value));
+                differences += 1;
+            }
+            Some(to_value) if to_value != from_value => {
+                println!("~ {}: {} -> {}", name, show(from_value), show(to_value));
+                differences += 1;
+            }
+            Some(_) => (),
+        }
+    }
+
+    for (name, to_value) in &to_vars {
+        if !from_vars.contains_key(name) {
+            println!("+ {}: {}", name, show(to_value));
+            differences += 1;
+        }
+    }
+
+    if differences == 0 {
+        println!(
+            "No differences between {} and {}",
+            from.environment, to.environment
+        );
+        Ok(())
+    } else {
+        Err(anyhow::Error::new(Outcome {
+            code: DIFFERENT,
+            message: format!(
+                "{} difference(s) between {} and {}",
+                differences, from.environment, to.environment
+            ),
+        }))
+    }
+}
+
+fn comparable_vars(
+    env: ResolvedEnv,
+    release: &Release,
+    salt: &RandomState,
+) -> BTreeMap<String, Comparable> {
+    for error in &env.errors {
+        log::warn!("{}: {}", release.name, error);
+    }
+
+    env.vars
+        .iter()
+        .filter(|var| !var.shadowed)
+        .map(|var| (var.name.to_string(), comparable(var, salt)))
+        .collect()
+}
+
+fn comparable(var: &ResolvedVar, salt: &RandomState) -> Comparable {
+    match &var.value {
+        // Field references such as metadata.name differ between any two pods,
+        // so compare what they refer to rather than their value.
+        ResolvedValue::FieldRef { path, .. } => Comparable::Reference(path.clone()),
+        value => match value.segments() {
+            Some(segments) => {
+                let contains_secret = segments
+                    .iter()
+                    .any(|segment| matches!(segment, Segment::Secret(_)));
+                let text: String = segments
+                    .into_iter()
+                    .map(|segment| match segment {
+                        Segment::Text(text) | Segment::Secret(text) => text,
+                    })
+                    .collect();
+                if contains_secret {
+                    Comparable::Secret(fingerprint(&text, salt))
+                } else {
+                    Comparable::Value(text)
+                }
+            }
+            None => Comparable::Missing,
+        },
+    }
+}
+
+fn fingerprint(value: &str, salt: &RandomState) -> u64 {
+    salt.hash_one(value)
+}
+
+fn show(value: &Comparable) -> String {
+    match value {
+        Comparable::Value(value) => format!("{:?}", value),
+        Comparable::Secret(fingerprint) => format!("(secret {:016x})", fingerprint),
+        Comparable::Reference(path) => format!("(reference to {})", path),
+        Comparable::Missing => String::from("(missing)"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    fn var(value: ResolvedValue) -> ResolvedVar {
+        ResolvedVar {
+            name: Rc::new(String::from("VAR")),
+            value,
+            shadowed: false,
+        }
+    }
+
+    fn secret(value: &str) -> ResolvedVar {
+        var(ResolvedValue::SecretKeyRef {
+            secret: Rc::new(String::from("app-secrets")),
+            key: Rc::new(String::from("password")),
+            value: Ok(Rc::new(String::from(value))),
+        })
+    }
+
+    fn pod(value: &str) -> ResolvedVar {
+        var(ResolvedValue::Pod {
+            value: Some(String::from(value)),
+            expanded: None,
+        })
+    }
+
+    #[test]
+    fn compares_secrets_by_fingerprint() {
+        let salt = RandomState::new();
+
+        let hunter2 = comparable(&secret("hunter2"), &salt);
+
+        assert_eq!(hunter2, comparable(&secret("hunter2"), &salt));
+        assert_ne!(hunter2, comparable(&secret("hunter3"), &salt));
+        assert_ne!(hunter2, comparable(&pod("hunter2"), &salt));
+        assert!(!show(&hunter2).contains("hunter2"));
+    }
+
+    #[test]
+    fn salts_fingerprints_per_run() {
+        assert_ne!(
+            comparable(&secret("hunter2"), &RandomState::new()),
+            comparable(&secret("hunter2"), &RandomState::new())
+        );
+    }
+
+    #[test]
+    fn compares_field_references_by_path() {
+        let salt = RandomState::new();
+        let field = |value: &str| {
+            var(ResolvedValue::FieldRef {
+                path: String::from("metadata.name"),
+                value: Some(String::from(value)),
+            })
+        };
+
+        assert_eq!(
+            comparable(&field("web-1"), &salt),
+            comparable(&field("web-2"), &salt)
+        );
+        assert_eq!(
+            comparable(&pod("staging"), &salt),
+            Comparable::Value(String::from("staging"))
+        );
+    }
+}
@@ -0,0 +1,336 @@
+use super::set::{self, Change, Kind, Options, Source};
+use super::Target;
+use crate::flightctl::kubeenv::ResolvedValue;
+use crate::flightctl::runner::{self, Runner};
+use crate::flightctl::{ApplicationConfig, Config, Release};
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+type Sources = BTreeMap<Source, BTreeMap<String, String>>;
+
+pub fn run(
+    runner: &dyn Runner,
+    config: &Config,
+    release: &Release,
+    target: &Target,
+    options: &Options,
+) -> anyhow::Result<()> {
+    let original = current_sources(runner, config, release, target)?;
+    if original.keys().any(|source| source.kind == Kind::Secret) {
+        super::confirm_reveal(release, "secrets")?;
+    }
+
+    let mut file = tempfile::Builder::new()
+        .prefix("flightctl-")
+        .suffix(".env")
+        .tempfile()?;
+    file.write_all(render(release, &original).as_bytes())?;
+    file.flush()?;
+
+    open_editor(runner, file.path())?;
+
+    let edited = parse(&std::fs::read_to_string(file.path())?, &original)?;
+    apply_edits(runner, config, release, original, edited, options)
+}
+
+/// The keys behind the release's variables, along with the application's
+/// default sources so new keys can be added to them.
+fn current_sources(
+    runner: &dyn Runner,
+    config: &Config,
+    release: &Release,
+    target: &Target,
+) -> anyhow::Result<Sources> {
+    let env = super::resolve(runner, config, release, target)?;
+    for error in &env.errors {
+        log::warn!("{}", error);
+    }
+
+    let mut sources = Sources::new();
+    for var in env.vars.iter().filter(|var| !var.shadowed) {
+        let (kind, name, key, value) = match &var.value {
+            ResolvedValue::ConfigMapKeyRef {
+                config_map,
+                key,
+                value: Ok(value),
+            } => (Kind::ConfigMap, config_map, key, value),
+            ResolvedValue::SecretKeyRef {
+                secret,
+                key,
+                value: Ok(value),
+            } => (Kind::Secret, secret, key, value),
+            _ => continue,
+        };
+        sources
+            .entry(Source {
+                kind,
+                name: name.to_string(),
+            })
+            .or_default()
+            .insert(key.to_string(), value.to_string());
+    }
+
+    let application = config.find_application(release)?;
+    match &application.config {
+        ApplicationConfig::Kubectl {
+            config_map, secret, ..
+        } => {
+            for (kind, name) in [(Kind::ConfigMap, config_map), (Kind::Secret, secret)] {
+                if let Some(name) = name {
+                    sources
+                        .entry(Source {
+                            kind,
+                            name: name.clone(),
+                        })
+                        .or_default();
+                }
+            }
+        }
+    }
+
+    Ok(sources)
+}
+
+fn render(release: &Release, sources: &Sources) -> String {
+    let mut buffer = format!(
+        "# Configuration for release {}, grouped by the ConfigMap or Secret each\n\
+         # key is stored in. Change a line to update a key, remove it to delete\n\
+         # the key, or add KEY=VALUE lines under a source to create new keys.\n\
+         # Values with line breaks or surrounding spaces are written as quoted\n\
+         # strings with escapes.\n",
+        release.name
+    );
+
+    for (source, values) in sources {
+        buffer.push_str(&format!("\n[{}]\n", source));
+        for (key, value) in values {
+            buffer.push_str(&format!("{}={}\n", key, quote(value)));
+        }
+    }
+
+    buffer
+}
+
+fn quote(value: &str) -> String {
+    if value.contains(['\n', '\r']) || value.starts_with('"') || value.trim() != value {
+        serde_json::Value::from(value).to_string()
+    } else {
+        String::from(value)
+    }
+}
+
+fn parse(buffer: &str, original: &Sources) -> anyhow::Result<Sources> {
+    let mut sources: Sources = original
+        .keys()
+        .map(|source| (source.clone(), BTreeMap::new()))
+        .collect();
+    let mut current: Option<&Source> = None;
+
+    for (index, line) in buffer.lines().enumerate() {
+        let line_number = index + 1;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        if let Some(header) = trimmed.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+            current = Some(
+                original
+                    .keys()
+                    .find(|source| source.to_string() == header)
+                    .ok_or_else(|| {
+                        anyhow::Error::msg(format!(
+                            "Line {}: {} isn't one of the sources being edited",
+                            line_number, header
+                        ))
+                    })?,
+            );
+            continue;
+        }
+
+        let source = current.ok_or_else(|| {
+            anyhow::Error::msg(format!(
+                "Line {}: expected a [configmap/NAME] or [secret/NAME] header first",
+                line_number
+            ))
+        })?;
+        let (key, value) =
+            set::parse_assignment(trimmed).with_context(|| format!("Line {}", line_number))?;
+        // Spaces around `=` are for readability; values that need them are
+        // quoted. Existing keys are kept as they are, but new ones have to
+        // work as variable names.
+        let key = String::from(key.trim_end());
+        if !original[source].contains_key(&key) && !super::is_portable_name(&key) {
+            return Err(anyhow::Error::msg(format!(
+                "Line {}: {} isn't a valid variable name",
+                line_number, key
+            )));
+        }
+        let value = value.trim_start();
+        let value = if value.starts_with('"') {
+            serde_json::from_str(value)
+                .with_context(|| format!("Line {}: invalid quoted value", line_number))?
+        } else {
+            String::from(value)
+        };
+
+        let values = sources.entry(source.clone()).or_default();
+        if values.insert(key.clone(), value).is_some() {
+            return Err(anyhow::Error::msg(format!(
+                "Line {}: {} is listed twice in {}",
+                line_number, key, source
+            )));
+        }
+    }
+
+    Ok(sources)
+}
+
+fn apply_edits(
+    runner: &dyn Runner,
+    config: &Config,
+    release: &Release,
+    original: Sources,
+    mut edited: Sources,
+    options: &Options,
+) -> anyhow::Result<()> {
+    let mut changes = Vec::new();
+
+    for (source, values) in original {
+        let mut edited_values = edited.remove(&source).unwrap_or_default();
+        for (key, before) in values {
+            let after = edited_values.remove(&key);
+            changes.push(Change {
+                source: source.clone(),
+                key,
+                before: Some(before),
+                after,
+            });
+        }
+        for (key, after) in edited_values {
+            changes.push(Change {
+                source: source.clone(),
+                key,
+                before: None,
+                after: Some(after),
+            });
+        }
+    }
+
+    set::apply(runner, config, release, changes, options)
+}
+
+/// Opens the file in `$VISUAL` or `$EDITOR`, falling back to `vi`. The
+/// variable may include arguments, such as `code --wait`.
+fn open_editor(runner: &dyn Runner, path: &std::path::Path) -> anyhow::Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| String::from("vi"));
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let mut args: Vec<String> = words.map(String::from).collect();
+    args.push(path.to_string_lossy().into_owned());
+
+    runner::run_print(runner, program, &args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(kind: Kind, name: &str) -> Source {
+        Source {
+            kind,
+            name: String::from(name),
+        }
+    }
+
+    fn original() -> Sources {
+        BTreeMap::from([
+            (
+                source(Kind::ConfigMap, "app-config"),
+                BTreeMap::from([
+                    (String::from("HOST"), String::from("example.com")),
+                    (String::from("log-level"), String::from("debug")),
+                ]),
+            ),
+            (source(Kind::Secret, "app-secrets"), BTreeMap::new()),
+        ])
+    }
+
+    fn error(buffer: &str) -> String {
+        parse(buffer, &original()).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn trims_spaces_around_the_equals_sign() {
+        let sources = parse(
+            "[configmap/app-config]\nHOST = example.org\nGREETING = \" hi \"\nlog-level=info\n",
+            &original(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            sources[&source(Kind::ConfigMap, "app-config")],
+            BTreeMap::from([
+                (String::from("GREETING"), String::from(" hi ")),
+                (String::from("HOST"), String::from("example.org")),
+                (String::from("log-level"), String::from("info")),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_variable_names() {
+        assert_eq!(
+            error("[configmap/app-config]\nLOG LEVEL=debug\n"),
+            "Line 2: LOG LEVEL isn't a valid variable name"
+        );
+        assert_eq!(
+            error("[configmap/app-config]\n1HOST=example.org\n"),
+            "Line 2: 1HOST isn't a valid variable name"
+        );
+    }
+
+    #[test]
+    fn parses_quoted_values_and_removed_keys() {
+        let sources = parse(
+            "# comment\n[configmap/app-config]\nHOST=\"a\\nb\"\n\n[secret/app-secrets]\nTOKEN=abc=def\n",
+            &original(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            sources[&source(Kind::ConfigMap, "app-config")],
+            BTreeMap::from([(String::from("HOST"), String::from("a\nb"))])
+        );
+        assert_eq!(
+            sources[&source(Kind::Secret, "app-secrets")],
+            BTreeMap::from([(String::from("TOKEN"), String::from("abc=def"))])
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert_eq!(
+            error("HOST=example.org\n"),
+            "Line 1: expected a [configmap/NAME] or [secret/NAME] header first"
+        );
+        assert_eq!(
+            error("[configmap/other]\n"),
+            "Line 1: configmap/other isn't one of the sources being edited"
+        );
+        assert_eq!(
+            error("[configmap/app-config]\nHOST=a\nHOST=b\n"),
+            "Line 3: HOST is listed twice in configmap/app-config"
+        );
+        assert_eq!(
+            error("[configmap/app-config]\nHOST=\"unterminated\n"),
+            "Line 2: invalid quoted value"
+        );
+        assert_eq!(error("[configmap/app-config]\nHOST\n"), "Line 2");
+    }
+}
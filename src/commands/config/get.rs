@@ -0,0 +1,161 @@
+use super::Target;
+use crate::flightctl::exit::Outcome;
+use crate::flightctl::kubeenv::{ResolvedEnv, ResolvedValue, Segment};
+use crate::flightctl::runner::Runner;
+use crate::flightctl::{Config, Release};
+
+/// Exit status when the variable isn't defined for the release.
+pub const UNDEFINED: i32 = 1;
+
+/// Exit status when the variable is defined but its value couldn't be
+/// resolved, such as a reference to a missing ConfigMap key.
+pub const UNRESOLVED: i32 = 2;
+
+/// Exit status when the variable is defined without any value.
+pub const UNSET: i32 = 3;
+
+/// Exit status when the value contains a secret and `--reveal` wasn't given.
+pub const CONCEALED: i32 = 4;
+
+pub fn run(
+    runner: &dyn Runner,
+    config: &Config,
+    release: &Release,
+    target: &Target,
+    key: &str,
+    reveal: bool,
+) -> anyhow::Result<()> {
+    let env = super::resolve(runner, config, release, target)?;
+    let (value, secret) = lookup(&env, key, reveal)?;
+    if secret {
+        super::confirm_reveal(release, &format!("secret {}", key))?;
+    }
+
+    println!("{}", value);
+    Ok(())
+}
+
+/// The variable's value, and whether any of it comes from a secret.
+fn lookup(env: &ResolvedEnv, key: &str, reveal: bool) -> anyhow::Result<(String, bool)> {
+    let var = env
+        .vars
+        .iter()
+        .find(|var| !var.shadowed && var.name.as_str() == key)
+        .ok_or_else(|| outcome(UNDEFINED, format!("{} isn't set", key)))?;
+
+    let segments = match &var.value {
+        ResolvedValue::Pod {
+            value: None,
+            expanded: None,
+        } => return Err(outcome(UNSET, format!("{} has no value", key))),
+        value => value
+            .segments()
+            .ok_or_else(|| outcome(UNRESOLVED, format!("{} couldn't be resolved", key)))?,
+    };
+
+    let mut value = String::new();
+    let mut secret = false;
+    for segment in segments {
+        match segment {
+            Segment::Text(text) => value.push_str(&text),
+            Segment::Secret(text) => {
+                value.push_str(&text);
+                secret = true;
+            }
+        }
+    }
+
+    if secret && !reveal {
+        return Err(outcome(
+            CONCEALED,
+            format!("{} contains a secret value; pass --reveal to print it", key),
+        ));
+    }
+    Ok((value, secret))
+}
+
+fn outcome(code: i32, message: String) -> anyhow::Error {
+    log::error!("{}", message);
+    anyhow::Error::new(Outcome { code, message })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flightctl::kubeenv::{Missing, ResolvedVar};
+    use std::rc::Rc;
+
+    fn var(name: &str, value: ResolvedValue) -> ResolvedVar {
+        ResolvedVar {
+            name: Rc::new(String::from(name)),
+            value,
+            shadowed: false,
+        }
+    }
+
+    fn env() -> ResolvedEnv {
+        ResolvedEnv {
+            vars: vec![
+                var(
+                    "RAILS_ENV",
+                    ResolvedValue::Pod {
+                        value: Some(String::from("staging")),
+                        expanded: None,
+                    },
+                ),
+                var(
+                    "EMPTY",
+                    ResolvedValue::Pod {
+                        value: None,
+                        expanded: None,
+                    },
+                ),
+                var(
+                    "LOG_LEVEL",
+                    ResolvedValue::ConfigMapKeyRef {
+                        config_map: Rc::new(String::from("app-config")),
+                        key: Rc::new(String::from("log-level")),
+                        value: Err(Missing::Key),
+                    },
+                ),
+                var(
+                    "DATABASE_PASSWORD",
+                    ResolvedValue::SecretKeyRef {
+                        secret: Rc::new(String::from("app-secrets")),
+                        key: Rc::new(String::from("password")),
+                        value: Ok(Rc::new(String::from("hunter2"))),
+                    },
+                ),
+            ],
+            errors: vec![],
+        }
+    }
+
+    fn code(result: anyhow::Result<(String, bool)>) -> i32 {
+        result.unwrap_err().downcast::<Outcome>().unwrap().code
+    }
+
+    #[test]
+    fn prints_plain_values() {
+        assert_eq!(
+            lookup(&env(), "RAILS_ENV", false).unwrap(),
+            (String::from("staging"), false)
+        );
+    }
+
+    #[test]
+    fn prints_secrets_only_when_revealed() {
+        assert_eq!(code(lookup(&env(), "DATABASE_PASSWORD", false)), CONCEALED);
+        assert_eq!(
+            lookup(&env(), "DATABASE_PASSWORD", true).unwrap(),
+            (String::from("hunter2"), true)
+        );
+    }
+
+    #[test]
+    fn exits_with_a_status_for_each_missing_value() {
+        assert_eq!(code(lookup(&env(), "HOST", false)), UNDEFINED);
+        assert_eq!(code(lookup(&env(), "LOG_LEVEL", false)), UNRESOLVED);
+        assert_eq!(code(lookup(&env(), "EMPTY", false)), UNSET);
+    }
+}
@@ -0,0 +1,422 @@
+use super::Target;
+use crate::flightctl::kubeclient;
+use crate::flightctl::kubeenv::{ResolvedEnv, ResolvedValue};
+use crate::flightctl::runner::Runner;
+use crate::flightctl::{prompt, ApplicationConfig, Config, Release};
+use k8s_openapi::api::core::v1 as k8s;
+use k8s_openapi::ByteString;
+use std::collections::BTreeMap;
+use std::fmt;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Options {
+    /// Restart the application's deployments so they pick up the change
+    #[structopt(long)]
+    pub restart: bool,
+
+    /// Apply the change without asking for confirmation
+    #[structopt(short, long)]
+    pub yes: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    ConfigMap,
+    Secret,
+}
+
+/// The ConfigMap or Secret a variable's value is stored in.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Source {
+    pub kind: Kind,
+    pub name: String,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            Kind::ConfigMap => write!(f, "configmap/{}", self.name),
+            Kind::Secret => write!(f, "secret/{}", self.name),
+        }
+    }
+}
+
+/// A single key to write to or remove from a source. `before` is `None` for
+/// keys that don't exist yet and `after` is `None` for keys being removed.
+#[derive(Debug)]
+pub struct Change {
+    pub source: Source,
+    pub key: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// Parses a `KEY=VALUE` argument.
+pub fn parse_assignment(assignment: &str) -> anyhow::Result<(String, String)> {
+    match assignment.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((String::from(key), String::from(value))),
+        _ => Err(anyhow::Error::msg(format!(
+            "Expected KEY=VALUE, got: {}",
+            assignment
+        ))),
+    }
+}
+
+pub fn set(
+    runner: &dyn Runner,
+    config: &Config,
+    release: &Release,
+    target: &Target,
+    assignments: &[(String, String)],
+    secret: bool,
+    options: &Options,
+) -> anyhow::Result<()> {
+    let env = super::resolve(runner, config, release, target)?;
+    let mut changes = Vec::new();
+
+    for (name, value) in assignments {
+        let (source, key, before) = match locate(&env, name)? {
+            Some(located) => located,
+            None => (default_source(config, release, secret)?, name.clone(), None),
+        };
+        changes.push(Change {
+            source,
+            key,
+            before,
+            after: Some(value.clone()),
+        });
+    }
+
+    apply(runner, config, release, changes, options)
+}
+
+pub fn unset(
+    runner: &dyn Runner,
+    config: &Config,
+    release: &Release,
+    target: &Target,
+    names: &[String],
+    options: &Options,
+) -> anyhow::Result<()> {
+    let env = super::resolve(runner, config, release, target)?;
+    let mut changes = Vec::new();
+
+    for name in names {
+        let (source, key, before) =
+            locate(&env, name)?.ok_or_else(|| anyhow::Error::msg(format!("{} isn't set", name)))?;
+        changes.push(Change {
+            source,
+            key,
+            before,
+            after: None,
+        });
+    }
+
+    apply(runner, config, release, changes, options)
+}
+
+/// Previews the changes, asks for confirmation, then patches each source
+/// once with all of its changes.
+pub fn apply(
+    runner: &dyn Runner,
+    config: &Config,
+    release: &Release,
+    changes: Vec<Change>,
+    options: &Options,
+) -> anyhow::Result<()> {
+    let mut sources: BTreeMap<Source, Vec<Change>> = BTreeMap::new();
+    for change in changes {
+        if change.before != change.after {
+            sources
+                .entry(change.source.clone())
+                .or_default()
+                .push(change);
+        }
+    }
+
+    if sources.is_empty() {
+        println!("No changes to apply to release {}", release.name);
+        return Ok(());
+    }
+
+    for (source, changes) in &sources {
+        println!("{}:", source);
+        for change in changes {
+            match (&change.before, &change.after) {
+                (None, Some(after)) => {
+                    println!("+ {}: {}", change.key, show(source, after))
+                }
+                (Some(before), Some(after)) => println!(
+                    "~ {}: {} -> {}",
+                    change.key,
+                    show(source, before),
+                    show(source, after)
+                ),
+                (Some(before), None) => {
+                    println!("- {}: {}", change.key, show(source, before))
+                }
+                (None, None) => (),
+            }
+        }
+    }
+
+    if !options.yes
+        && !prompt::confirm(&format!("Apply these changes to release {}?", release.name))?
+    {
+        return Err(anyhow::Error::msg("Change cancelled"));
+    }
+
+    apply_with(
+        &kubeclient::new(runner, config, release),
+        config,
+        release,
+        &sources,
+        options,
+    )
+}
+
+fn apply_with(
+    client: &kubeclient::KubeClient,
+    config: &Config,
+    release: &Release,
+    sources: &BTreeMap<Source, Vec<Change>>,
+    options: &Options,
+) -> anyhow::Result<()> {
+    for (source, changes) in sources {
+        let patch = patch(source, changes);
+        match source.kind {
+            Kind::ConfigMap => client.patch::<k8s::ConfigMap>(&source.name, &patch)?,
+            Kind::Secret => client.patch::<k8s::Secret>(&source.name, &patch)?,
+        }
+    }
+
+    let application = config.find_application(release)?;
+    match &application.config {
+        ApplicationConfig::Kubectl { selector, .. } => {
+            if options.restart {
+                client.restart_workloads(kubeclient::Selector::new(selector.clone()))
+            } else {
+                log::info!(
+                    "Running pods keep their current configuration until restarted; pass --restart to restart them"
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Finds the source and key behind the variable that reaches the container,
+/// along with its current value. Variables defined directly in the pod spec
+/// can't be changed here since they belong to the release's manifests.
+fn locate(
+    env: &ResolvedEnv,
+    name: &str,
+) -> anyhow::Result<Option<(Source, String, Option<String>)>> {
+    let var = match env
+        .vars
+        .iter()
+        .find(|var| !var.shadowed && var.name.as_str() == name)
+    {
+        Some(var) => var,
+        None => return Ok(None),
+    };
+
+    let (kind, source, key, value) = match &var.value {
+        ResolvedValue::ConfigMapKeyRef {
+            config_map,
+            key,
+            value,
+        } => (Kind::ConfigMap, config_map, key, value),
+        ResolvedValue::SecretKeyRef { secret, key, value } => (Kind::Secret, secret, key, value),
+        _ => {
+            return Err(anyhow::Error::msg(format!(
+                "{} is defined in the pod spec; change it in the release's manifests instead",
+                name
+            )))
+        }
+    };
+
+    Ok(Some((
+        Source {
+            kind,
+            name: source.to_string(),
+        },
+        key.to_string(),
+        value.as_ref().ok().map(|value| value.to_string()),
+    )))
+}
+
+fn default_source(config: &Config, release: &Release, secret: bool) -> anyhow::Result<Source> {
+    let application = config.find_application(release)?;
+    match &application.config {
+        ApplicationConfig::Kubectl {
+            config_map,
+            secret: default_secret,
+            ..
+        } => {
+            let (kind, name, param) = if secret {
+                (Kind::Secret, default_secret, "secret")
+            } else {
+                (Kind::ConfigMap, config_map, "configMap")
+            };
+            match name {
+                Some(name) => Ok(Source {
+                    kind,
+                    name: name.clone(),
+                }),
+                None => Err(anyhow::Error::msg(format!(
+                    "Application {} has no default {} for new variables",
+                    application.name, param
+                ))),
+            }
+        }
+    }
+}
+
+/// Builds a merge patch for a source. Removed keys are set to `null`, and
+/// secret values are encoded as the API expects.
+fn patch(source: &Source, changes: &[Change]) -> serde_json::Value {
+    match source.kind {
+        Kind::ConfigMap => {
+            let data: BTreeMap<&str, Option<&str>> = changes
+                .iter()
+                .map(|change| (change.key.as_str(), change.after.as_deref()))
+                .collect();
+            serde_json::json!({ "data": data })
+        }
+        Kind::Secret => {
+            let data: BTreeMap<&str, Option<ByteString>> = changes
+                .iter()
+                .map(|change| {
+                    (
+                        change.key.as_str(),
+                        change
+                            .after
+                            .as_ref()
+                            .map(|value| ByteString(value.clone().into_bytes())),
+                    )
+                })
+                .collect();
+            serde_json::json!({ "data": data })
+        }
+    }
+}
+
+fn show(source: &Source, value: &str) -> String {
+    match source.kind {
+        Kind::ConfigMap => format!("{:?}", value),
+        Kind::Secret => String::from("********"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flightctl::runner::DryRun;
+    use crate::flightctl::testing::{self, FakeApi, FakeRunner};
+    use serde_json::json;
+
+    const SECRET: &str = "/api/v1/namespaces/default/secrets/app-secrets";
+
+    fn api() -> FakeApi {
+        FakeApi::new().serve(
+            SECRET,
+            json!({
+                "apiVersion": "v1",
+                "kind": "Secret",
+                "metadata": { "name": "app-secrets" },
+                "data": { "password": "aHVudGVyMg==" },
+            }),
+        )
+    }
+
+    fn sources() -> BTreeMap<Source, Vec<Change>> {
+        let source = Source {
+            kind: Kind::Secret,
+            name: String::from("app-secrets"),
+        };
+        BTreeMap::from([(
+            source.clone(),
+            vec![Change {
+                source,
+                key: String::from("password"),
+                before: Some(String::from("hunter2")),
+                after: Some(String::from("correct horse")),
+            }],
+        )])
+    }
+
+    fn options() -> Options {
+        Options {
+            restart: true,
+            yes: true,
+        }
+    }
+
+    #[test]
+    fn patches_secrets_without_passing_values_to_commands() {
+        let config = testing::config();
+        let release = testing::release(&config, "app-staging");
+        let runner = FakeRunner::new().expect(
+            "kubectl",
+            &[
+                "--context",
+                "test",
+                "rollout",
+                "restart",
+                "deploy",
+                "--selector",
+                "app=web",
+            ],
+        );
+        let api = api();
+
+        apply_with(
+            &api.client(&runner),
+            &config,
+            release,
+            &sources(),
+            &options(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            api.patches(),
+            [(
+                String::from(SECRET),
+                json!({ "data": { "password": "Y29ycmVjdCBob3JzZQ==" } })
+            )]
+        );
+        for call in runner.calls() {
+            for arg in call {
+                assert!(
+                    !arg.contains("Y29ycmVjdCBob3JzZQ==") && !arg.contains("correct horse"),
+                    "secret passed to a command: {}",
+                    arg
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dry_run_patches_nothing() {
+        let config = testing::config();
+        let release = testing::release(&config, "app-staging");
+        let fake = FakeRunner::new();
+        let runner = DryRun::new(&fake);
+        let api = api();
+
+        apply_with(
+            &api.client(&runner),
+            &config,
+            release,
+            &sources(),
+            &options(),
+        )
+        .unwrap();
+
+        assert!(api.patches().is_empty());
+        assert!(fake.calls().is_empty());
+    }
+}
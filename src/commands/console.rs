@@ -1,12 +1,19 @@
-use crate::flightctl::kubeclient;
+use crate::flightctl::kubeclient::{self, ExecOptions};
+use crate::flightctl::runner::Runner;
 use crate::flightctl::{ApplicationConfig, Config, Console, Release};
 
-pub fn run_default(config: &Config, release: &Release) -> anyhow::Result<()> {
+pub fn run_default(runner: &dyn Runner, config: &Config, release: &Release) -> anyhow::Result<()> {
     let application = config.find_application(&release)?;
 
     match &application.config {
         ApplicationConfig::Kubectl { console, .. } => match console {
-            Some(Console::Exec { command, .. }) => run_command(config, release, command),
+            Some(Console::Exec { command, .. }) => run_command(
+                runner,
+                config,
+                release,
+                command,
+                &ExecOptions::detect(false, false),
+            ),
             None => Err(anyhow::Error::msg(format!(
                 "No console configured for application: {}",
                 application.name
@@ -15,12 +22,20 @@ pub fn run_default(config: &Config, release: &Release) -> anyhow::Result<()> {
     }
 }
 
-pub fn run_command(config: &Config, release: &Release, cmd: &Vec<String>) -> anyhow::Result<()> {
+pub fn run_command(
+    runner: &dyn Runner,
+    config: &Config,
+    release: &Release,
+    cmd: &Vec<String>,
+    options: &ExecOptions,
+) -> anyhow::Result<()> {
     let application = config.find_application(&release)?;
 
     match &application.config {
-        ApplicationConfig::Kubectl { console, selector } => {
-            let client = kubeclient::new(&release.context);
+        ApplicationConfig::Kubectl {
+            console, selector, ..
+        } => {
+            let client = kubeclient::new(runner, config, release);
             let base_selector = kubeclient::Selector::new(selector.clone());
 
             match console {
@@ -32,7 +47,7 @@ pub fn run_command(config: &Config, release: &Release, cmd: &Vec<String>) -> any
                     let console_selector =
                         base_selector.extend(&kubeclient::Selector::new(selector.clone()));
                     let pod = client.get_available_pod(console_selector)?;
-                    client.exec(&pod, container, cmd)?;
+                    client.exec(&pod, container, cmd, options)?;
                     Ok(())
                 }
                 None => Err(anyhow::Error::msg(format!(
@@ -0,0 +1,92 @@
+use crate::flightctl::authorize;
+use crate::flightctl::cluster_cache::ClusterCache;
+use crate::flightctl::context;
+use crate::flightctl::exit::{Failure, Outcome};
+use crate::flightctl::runner::Runner;
+use crate::flightctl::{Config, Release};
+use anyhow::Context;
+
+/// Exit status when checking finds drift.
+pub const DRIFTED: i32 = 1;
+
+/// Reports how each release's Kubernetes context, credentials and cluster
+/// differ from the workspace configuration, and repairs them unless only
+/// checking. A missing AWS profile or expired session counts as a difference
+/// when checking.
+pub fn sync(
+    runner: &dyn Runner,
+    cache: &ClusterCache,
+    config: &Config,
+    releases: &[&Release],
+    check: bool,
+) -> anyhow::Result<()> {
+    let mut contexts: Vec<&str> = vec![];
+    let mut drifted = 0;
+    for release in releases {
+        // Releases sharing a context would only report the same drift again.
+        if contexts.contains(&release.context.as_str()) {
+            continue;
+        }
+        contexts.push(&release.context);
+
+        // Checking mustn't create a profile or start a login, and without
+        // credentials there's no cluster to compare against.
+        if check {
+            let problem =
+                authorize::check(runner, config, release).context(Failure::Authorization)?;
+            if let Some(problem) = problem {
+                println!("{}", problem);
+                drifted += 1;
+                continue;
+            }
+        } else {
+            authorize::run(runner, config, release).context(Failure::Authorization)?;
+        }
+        let drift =
+            context::sync(runner, cache, config, release, check).context(Failure::Context)?;
+        if drift.is_empty() {
+            println!("Context {} is up to date", release.context);
+        }
+        for drift in &drift {
+            println!("{}", drift);
+        }
+        drifted += drift.len();
+    }
+
+    if check && drifted > 0 {
+        Err(anyhow::Error::new(Outcome {
+            code: DRIFTED,
+            message: format!("{} difference(s)", drifted),
+        }))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flightctl::exit;
+    use crate::flightctl::testing::{self, FakeRunner};
+
+    #[test]
+    fn checks_an_expired_session_without_logging_in() {
+        let config = testing::config();
+        let runner = FakeRunner::new()
+            .expect("aws", &["configure", "list-profiles"])
+            .stdout("sso\n")
+            .expect("aws", &["--profile", "sso", "sts", "get-caller-identity"])
+            .fails(255, "The SSO session has expired");
+
+        let err = sync(
+            &runner,
+            &ClusterCache::disabled(),
+            &config,
+            &[testing::release(&config, "app-staging")],
+            true,
+        )
+        .unwrap_err();
+
+        assert_eq!(exit::code(&err), DRIFTED);
+    }
+}
@@ -1,12 +1,18 @@
 use crate::flightctl::kubeclient;
+use crate::flightctl::runner::Runner;
 use crate::flightctl::{ApplicationConfig, Config, Release};
 
-pub fn run(config: &Config, release: &Release, cmd: &Vec<String>) -> anyhow::Result<()> {
+pub fn run(
+    runner: &dyn Runner,
+    config: &Config,
+    release: &Release,
+    cmd: &Vec<String>,
+) -> anyhow::Result<()> {
     let application = config.find_application(&release)?;
 
     match &application.config {
         ApplicationConfig::Kubectl { .. } => {
-            let client = kubeclient::new(&release.context);
+            let client = kubeclient::new(runner, config, release);
             client.run_command(cmd)
         }
     }
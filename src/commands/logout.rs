@@ -0,0 +1,379 @@
+use crate::flightctl::aws;
+use crate::flightctl::cluster_cache::ClusterCache;
+use crate::flightctl::context::{is_own_cluster, is_own_user};
+use crate::flightctl::kubeconfig_writer::{self, Names};
+use crate::flightctl::prompt;
+use crate::flightctl::runner::Runner;
+use crate::flightctl::{Config, Context, Release};
+use kube::config::Kubeconfig;
+
+/// What flightctl set up for some releases and is still there.
+#[derive(Debug, Default, PartialEq)]
+pub struct Artifacts {
+    /// AWS profiles flightctl created.
+    pub profiles: Vec<String>,
+    pub kubeconfig: Names,
+}
+
+/// Lists what flightctl set up for the releases, then removes it once
+/// confirmed. Clusters and AWS profiles other releases still use are kept,
+/// as are entries with the same names that flightctl didn't write.
+/// Signing out of SSO affects every session on the machine, so it's only
+/// done when asked for.
+pub fn run(
+    runner: &dyn Runner,
+    cache: &ClusterCache,
+    config: &Config,
+    releases: &[&Release],
+    sso_logout: bool,
+    yes: bool,
+) -> anyhow::Result<()> {
+    let file = config.kubeconfig_file();
+    let artifacts = find(runner, cache, config, &file.read()?, releases)?;
+    if artifacts.profiles.is_empty() && artifacts.kubeconfig.is_empty() && !sso_logout {
+        println!("Nothing to remove");
+        return Ok(());
+    }
+
+    for profile in &artifacts.profiles {
+        println!("AWS profile {}", profile);
+    }
+    if sso_logout {
+        println!("Every AWS SSO session on this machine, with `aws sso logout`");
+    }
+    if !artifacts.kubeconfig.is_empty() {
+        let path = file.write_path()?;
+        for entry in artifacts.kubeconfig.entries() {
+            println!("Kubernetes {} in {}", entry, path.display());
+        }
+    }
+
+    if !yes && !prompt::confirm("Remove these?")? {
+        return Err(anyhow::Error::msg("Logout cancelled"));
+    }
+
+    kubeconfig_writer::remove(runner, &file, artifacts.kubeconfig)?;
+    if sso_logout {
+        aws::sso_logout(runner)?;
+    }
+    for profile in &artifacts.profiles {
+        aws::delete_profile(runner, profile)?;
+    }
+    Ok(())
+}
+
+fn find(
+    runner: &dyn Runner,
+    cache: &ClusterCache,
+    config: &Config,
+    kubeconfig: &Kubeconfig,
+    releases: &[&Release],
+) -> anyhow::Result<Artifacts> {
+    let mut removed: Vec<&Context> = vec![];
+    for release in releases {
+        let context = config.find_context(release)?;
+        if !removed.iter().any(|other| other.name == context.name) {
+            removed.push(context);
+        }
+    }
+    let kept: Vec<&Context> = config
+        .releases
+        .iter()
+        .filter(|release| !releases.iter().any(|other| other.name == release.name))
+        .filter_map(|release| config.find_context(release).ok())
+        .collect();
+
+    let file = config.kubeconfig_file();
+    let mut artifacts = Artifacts::default();
+    let mut profiles: Vec<&str> = vec![];
+    let mut clusters: Vec<String> = vec![];
+    for context in removed {
+        if kept.iter().any(|other| other.name == context.name) {
+            log::info!("Keeping context {}, which other releases use", context.name);
+            continue;
+        }
+        let auth = config.find_auth(context)?;
+        let cluster = config.find_cluster(context)?;
+
+        let name = file.name(&context.name);
+        let own_user = match kubeconfig
+            .auth_infos
+            .iter()
+            .find(|entry| entry.name == name)
+        {
+            Some(entry) if is_own_user(entry, auth) => {
+                artifacts.kubeconfig.users.push(name.clone());
+                true
+            }
+            Some(_) => {
+                log::info!(
+                    "Keeping Kubernetes user {}, which flightctl didn't create",
+                    name
+                );
+                false
+            }
+            None => false,
+        };
+
+        let cluster_name = file.name(&context.cluster);
+        let own_cluster = kubeconfig
+            .clusters
+            .iter()
+            .find(|entry| entry.name == cluster_name)
+            .is_some_and(|entry| is_own_cluster(cache, entry, cluster, auth));
+        if !kept.iter().any(|other| other.cluster == context.cluster)
+            && !clusters.contains(&cluster_name)
+            && kubeconfig
+                .clusters
+                .iter()
+                .any(|entry| entry.name == cluster_name)
+        {
+            clusters.push(cluster_name.clone());
+            if own_cluster {
+                artifacts.kubeconfig.clusters.push(cluster_name.clone());
+            } else {
+                log::info!(
+                    "Keeping Kubernetes cluster {}, which flightctl didn't create",
+                    cluster_name
+                );
+            }
+        }
+
+        if let Some(entry) = kubeconfig.contexts.iter().find(|entry| entry.name == name) {
+            let points_at_own = own_user
+                && own_cluster
+                && entry
+                    .context
+                    .as_ref()
+                    .is_some_and(|target| target.user == name && target.cluster == cluster_name);
+            if points_at_own {
+                artifacts.kubeconfig.contexts.push(name);
+            } else {
+                log::info!(
+                    "Keeping Kubernetes context {}, which flightctl didn't create",
+                    name
+                );
+            }
+        }
+
+        if kept.iter().any(|other| other.auth == context.auth)
+            || profiles.contains(&context.auth.as_str())
+        {
+            continue;
+        }
+        profiles.push(&context.auth);
+        if !aws::profile_exists(runner, &context.auth)? {
+            continue;
+        }
+        if aws::is_managed(runner, &context.auth)? {
+            artifacts.profiles.push(context.auth.clone());
+        } else {
+            log::info!(
+                "Keeping AWS profile {}, which flightctl didn't create",
+                context.auth
+            );
+        }
+    }
+    Ok(artifacts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flightctl::kubeconfig::KubeconfigMode;
+    use crate::flightctl::runner::DryRun;
+    use crate::flightctl::testing::{self, FakeRunner};
+
+    const KUBECONFIG: &str = r#"
+apiVersion: v1
+kind: Config
+clusters:
+- name: acme-cluster
+  cluster:
+    server: https://example.eks.amazonaws.com
+users:
+- name: acme-staging
+  user: &token
+    exec:
+      apiVersion: client.authentication.k8s.io/v1beta1
+      command: aws
+      args: [--region, us-east-1, eks, get-token, --cluster-name, example]
+      env:
+      - name: AWS_PROFILE
+        value: sso
+- name: acme-production
+  user: *token
+contexts:
+- name: acme-staging
+  context: {cluster: acme-cluster, user: acme-staging}
+- name: acme-production
+  context: {cluster: acme-cluster, user: acme-production}
+"#;
+
+    /// The user's own entries, named like the fixture's contexts.
+    const FOREIGN: &str = r#"
+apiVersion: v1
+kind: Config
+clusters:
+- name: cluster
+  cluster:
+    server: https://kubernetes.example.com
+users:
+- name: production
+  user:
+    token: secret
+- name: staging
+  user:
+    exec:
+      apiVersion: client.authentication.k8s.io/v1beta1
+      command: aws
+      args: [--region, us-east-1, eks, get-token, --cluster-name, example]
+      env:
+      - name: AWS_PROFILE
+        value: sso
+contexts:
+- name: production
+  context: {cluster: cluster, user: production}
+- name: staging
+  context: {cluster: cluster, user: staging}
+"#;
+
+    const LIST_PROFILES: [&str; 2] = ["configure", "list-profiles"];
+    const GET_MANAGED: [&str; 5] = ["--profile", "sso", "configure", "get", "flightctl_managed"];
+
+    /// The fixture as a workspace named acme whose own kubeconfig has both
+    /// releases' entries, with the cluster's endpoint cached.
+    fn workspace() -> (tempfile::TempDir, Config, ClusterCache) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = testing::config();
+        config.kubeconfig = KubeconfigMode::Workspace;
+        config.root = dir.path().join("acme");
+        let path = config.kubeconfig_file().write_path().unwrap();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, KUBECONFIG).unwrap();
+        let cache = dir.path().join("eks-clusters.json");
+        std::fs::write(
+            &cache,
+            r#"{"sso/us-east-1/example": {
+                "cluster": {"endpoint": "https://example.eks.amazonaws.com", "cert": ""},
+                "fetched_at": 0
+            }}"#,
+        )
+        .unwrap();
+        (dir, config, ClusterCache::at(cache))
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| String::from(*name)).collect()
+    }
+
+    #[test]
+    fn finds_everything_for_the_workspace() {
+        let (_dir, config, cache) = workspace();
+        let kubeconfig = Kubeconfig::from_yaml(KUBECONFIG).unwrap();
+        let runner = FakeRunner::new()
+            .expect("aws", &LIST_PROFILES)
+            .stdout("default\nsso\n")
+            .expect("aws", &GET_MANAGED)
+            .stdout("true\n");
+        let releases: Vec<&Release> = config.releases.iter().collect();
+
+        let artifacts = find(&runner, &cache, &config, &kubeconfig, &releases).unwrap();
+
+        assert_eq!(
+            artifacts,
+            Artifacts {
+                profiles: names(&["sso"]),
+                kubeconfig: Names {
+                    users: names(&["acme-staging", "acme-production"]),
+                    clusters: names(&["acme-cluster"]),
+                    contexts: names(&["acme-staging", "acme-production"]),
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_profiles_flightctl_did_not_create() {
+        let (_dir, config, cache) = workspace();
+        let kubeconfig = Kubeconfig::from_yaml(KUBECONFIG).unwrap();
+        let runner = FakeRunner::new()
+            .expect("aws", &LIST_PROFILES)
+            .stdout("sso\n")
+            .expect("aws", &GET_MANAGED)
+            .fails(1, "");
+        let releases: Vec<&Release> = config.releases.iter().collect();
+
+        let artifacts = find(&runner, &cache, &config, &kubeconfig, &releases).unwrap();
+
+        assert_eq!(artifacts.profiles, Vec::<String>::new());
+        assert_eq!(artifacts.kubeconfig.contexts.len(), 2);
+    }
+
+    #[test]
+    fn keeps_kubeconfig_entries_flightctl_did_not_write() {
+        let (_dir, mut config, cache) = workspace();
+        config.kubeconfig = KubeconfigMode::Global;
+        let kubeconfig = Kubeconfig::from_yaml(FOREIGN).unwrap();
+        let runner = FakeRunner::new()
+            .expect("aws", &LIST_PROFILES)
+            .stdout("sso\n")
+            .expect("aws", &GET_MANAGED)
+            .stdout("true\n");
+        let releases: Vec<&Release> = config.releases.iter().collect();
+
+        let artifacts = find(&runner, &cache, &config, &kubeconfig, &releases).unwrap();
+
+        assert_eq!(
+            artifacts.kubeconfig,
+            Names {
+                users: names(&["staging"]),
+                clusters: vec![],
+                contexts: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_what_other_releases_use() {
+        let (_dir, config, cache) = workspace();
+        let kubeconfig = Kubeconfig::from_yaml(KUBECONFIG).unwrap();
+        let runner = FakeRunner::new();
+
+        let artifacts = find(
+            &runner,
+            &cache,
+            &config,
+            &kubeconfig,
+            &[testing::release(&config, "app-staging")],
+        )
+        .unwrap();
+
+        assert_eq!(artifacts.profiles, Vec::<String>::new());
+        assert_eq!(
+            artifacts.kubeconfig,
+            Names {
+                users: names(&["acme-staging"]),
+                clusters: vec![],
+                contexts: names(&["acme-staging"]),
+            }
+        );
+    }
+
+    #[test]
+    fn dry_run_removes_nothing() {
+        let (_dir, config, cache) = workspace();
+        let fake = FakeRunner::new()
+            .expect("aws", &LIST_PROFILES)
+            .stdout("sso\n")
+            .expect("aws", &GET_MANAGED)
+            .stdout("true\n");
+        let releases: Vec<&Release> = config.releases.iter().collect();
+
+        run(&DryRun::new(&fake), &cache, &config, &releases, true, true).unwrap();
+
+        assert_eq!(fake.calls().len(), 2);
+        let path = config.kubeconfig_file().write_path().unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), KUBECONFIG);
+    }
+}
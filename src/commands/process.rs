@@ -1,12 +1,13 @@
 use crate::flightctl::kubeclient;
+use crate::flightctl::runner::Runner;
 use crate::flightctl::{ApplicationConfig, Config, Release};
 
-pub fn run(config: &Config, release: &Release) -> anyhow::Result<()> {
+pub fn run(runner: &dyn Runner, config: &Config, release: &Release) -> anyhow::Result<()> {
     let application = config.find_application(&release)?;
 
     match &application.config {
         ApplicationConfig::Kubectl { selector, .. } => {
-            let client = kubeclient::new(&release.context);
+            let client = kubeclient::new(runner, config, release);
             client.get_workloads(kubeclient::Selector::new(selector.clone()))
         }
     }
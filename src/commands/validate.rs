@@ -0,0 +1,24 @@
+use crate::flightctl::ConfigFile;
+
+pub fn run(config_file: &ConfigFile) -> anyhow::Result<()> {
+    let problems = config_file.config.validate();
+    let path = config_file.path.display();
+
+    for problem in &problems {
+        match config_file.line_number(&problem.location) {
+            Some(line) => println!("{}:{}: {}", path, line, problem),
+            None => println!("{}: {}", path, problem),
+        }
+    }
+
+    if problems.is_empty() {
+        println!("{}: OK", path);
+        Ok(())
+    } else {
+        Err(anyhow::Error::msg(format!(
+            "Found {} problem(s) in {}",
+            problems.len(),
+            path
+        )))
+    }
+}
@@ -3,11 +3,49 @@ mod selector;
 
 pub mod authorize;
 pub mod aws;
+pub mod cluster_cache;
 pub mod context;
+pub mod downward;
+pub mod exit;
 pub mod kubeclient;
+pub mod kubeconfig;
 pub mod kubeconfig_writer;
 pub mod kubectl;
 pub mod kubeenv;
+pub mod prompt;
+pub mod runner;
+#[cfg(test)]
+pub mod testing;
+pub mod validate;
 
 pub use config::*;
 pub use selector::*;
+
+use std::path::PathBuf;
+
+/// The user's home directory, where tools such as `kubectl` and `aws` keep
+/// their configuration.
+pub fn home_dir() -> anyhow::Result<PathBuf> {
+    let variable = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    std::env::var_os(variable)
+        .map(PathBuf::from)
+        .ok_or_else(|| {
+            anyhow::Error::msg(format!(
+                "Can't find your home directory: {} isn't set",
+                variable
+            ))
+        })
+}
+
+/// Where flightctl keeps data it can fetch again, such as cluster details.
+pub fn cache_dir() -> anyhow::Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir).join("flightctl"));
+    }
+    if cfg!(windows) {
+        if let Some(dir) = std::env::var_os("LOCALAPPDATA") {
+            return Ok(PathBuf::from(dir).join("flightctl"));
+        }
+    }
+    Ok(home_dir()?.join(".cache").join("flightctl"))
+}
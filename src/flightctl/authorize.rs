@@ -1,8 +1,10 @@
 use super::aws;
 use super::config::{Auth, AuthConfig, Config, Release};
+use super::exit::Outcome;
+use super::runner::Runner;
 use log;
 
-pub fn run(config: &Config, release: &Release) -> anyhow::Result<()> {
+pub fn run(runner: &dyn Runner, config: &Config, release: &Release) -> anyhow::Result<()> {
     log::debug!("Beginning authorization");
     if log::log_enabled!(log::Level::Debug) {
         log::debug!("Checking configured auth for release: {:?}", release);
@@ -15,29 +17,70 @@ pub fn run(config: &Config, release: &Release) -> anyhow::Result<()> {
     if log::log_enabled!(log::Level::Debug) {
         log::debug!("Found auth: {:?}", auth);
     }
-    ensure_auth(auth)?;
+    ensure_auth(runner, auth)?;
     log::debug!("Authorization successful");
     Ok(())
 }
 
-fn ensure_auth(auth: &Auth) -> anyhow::Result<()> {
+/// Checks the release's credentials without creating a profile or logging
+/// in, describing what `run` would have to fix.
+pub fn check(
+    runner: &dyn Runner,
+    config: &Config,
+    release: &Release,
+) -> anyhow::Result<Option<String>> {
+    let context = config.find_context(release)?;
+    let auth = config.find_auth(context)?;
+    match &auth.config {
+        AuthConfig::AwsSso { .. } => {
+            if !aws::profile_exists(runner, &auth.name)? {
+                Ok(Some(format!("AWS profile {} is missing", auth.name)))
+            } else if aws::verify_auth(runner, &auth.name).is_err() {
+                Ok(Some(format!(
+                    "AWS profile {} has no valid SSO session",
+                    auth.name
+                )))
+            } else {
+                Ok(None)
+            }
+        }
+    }
+}
+
+fn ensure_auth(runner: &dyn Runner, auth: &Auth) -> anyhow::Result<()> {
     match &auth.config {
         AuthConfig::AwsSso { config: sso_config } => {
             log::info!("Authorizing using AWS SSO");
-            ensure_aws_profile(&auth.name, || aws::create_profile(&auth.name, sso_config))?;
-            aws::verify_auth(&auth.name).or_else(|_| {
-                aws::sso_login(&auth.name)?;
-                aws::verify_auth(&auth.name)
+            ensure_aws_profile(runner, &auth.name, || {
+                aws::create_profile(runner, &auth.name, sso_config)
+            })?;
+            aws::verify_auth(runner, &auth.name).or_else(|_| {
+                if aws::sso_login(runner, &auth.name)? {
+                    aws::verify_auth(runner, &auth.name)
+                } else {
+                    Err(dry_run_stopped())
+                }
             })
         }
     }
 }
 
-fn ensure_aws_profile<F>(name: &String, or: F) -> anyhow::Result<()>
+/// Ends a dry run successfully once it reaches a login it didn't run, since
+/// everything after it needs the session.
+fn dry_run_stopped() -> anyhow::Error {
+    let message = "Stopping the dry run here: the rest needs the SSO session the login would start";
+    log::info!("{}", message);
+    anyhow::Error::new(Outcome {
+        code: 0,
+        message: String::from(message),
+    })
+}
+
+fn ensure_aws_profile<F>(runner: &dyn Runner, name: &String, or: F) -> anyhow::Result<()>
 where
     F: FnOnce() -> anyhow::Result<()>,
 {
-    let profile_exists = aws::profile_exists(name)?;
+    let profile_exists = aws::profile_exists(runner, name)?;
 
     if profile_exists {
         Ok(())
@@ -45,3 +88,158 @@ where
         or()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flightctl::exit;
+    use crate::flightctl::runner::DryRun;
+    use crate::flightctl::testing::{self, FakeRunner};
+
+    const LIST_PROFILES: [&str; 2] = ["configure", "list-profiles"];
+    const VERIFY: [&str; 4] = ["--profile", "sso", "sts", "get-caller-identity"];
+    const LOGIN: [&str; 4] = ["--profile", "sso", "sso", "login"];
+
+    #[test]
+    fn uses_existing_profile_and_session() {
+        let config = testing::config();
+        let runner = FakeRunner::new()
+            .expect("aws", &LIST_PROFILES)
+            .stdout("default\nsso\n")
+            .expect("aws", &VERIFY);
+
+        run(&runner, &config, testing::release(&config, "app-staging")).unwrap();
+    }
+
+    #[test]
+    fn creates_missing_profile() {
+        let config = testing::config();
+        let runner = FakeRunner::new()
+            .expect("aws", &LIST_PROFILES)
+            .stdout("default\n")
+            .expect(
+                "aws",
+                &[
+                    "--profile",
+                    "sso",
+                    "configure",
+                    "set",
+                    "sso_region",
+                    "us-east-1",
+                ],
+            )
+            .expect(
+                "aws",
+                &[
+                    "--profile",
+                    "sso",
+                    "configure",
+                    "set",
+                    "sso_start_url",
+                    "https://example.awsapps.com/start",
+                ],
+            )
+            .expect(
+                "aws",
+                &[
+                    "--profile",
+                    "sso",
+                    "configure",
+                    "set",
+                    "flightctl_managed",
+                    "true",
+                ],
+            )
+            .expect("aws", &VERIFY);
+
+        run(&runner, &config, testing::release(&config, "app-staging")).unwrap();
+    }
+
+    #[test]
+    fn logs_in_when_session_expired() {
+        let config = testing::config();
+        let runner = FakeRunner::new()
+            .expect("aws", &LIST_PROFILES)
+            .stdout("sso\n")
+            .expect("aws", &VERIFY)
+            .fails(255, "The SSO session has expired")
+            .expect("aws", &LOGIN)
+            .expect("aws", &VERIFY);
+
+        run(&runner, &config, testing::release(&config, "app-staging")).unwrap();
+    }
+
+    #[test]
+    fn forwards_failed_login() {
+        let config = testing::config();
+        let runner = FakeRunner::new()
+            .expect("aws", &LIST_PROFILES)
+            .stdout("sso\n")
+            .expect("aws", &VERIFY)
+            .fails(255, "The SSO session has expired")
+            .expect("aws", &LOGIN)
+            .fails(130, "");
+
+        let err = run(&runner, &config, testing::release(&config, "app-staging")).unwrap_err();
+        assert_eq!(exit::code(&err), 130);
+    }
+
+    #[test]
+    fn reports_stderr_when_listing_profiles_fails() {
+        let config = testing::config();
+        let runner = FakeRunner::new()
+            .expect("aws", &LIST_PROFILES)
+            .fails(1, "aws: command not configured");
+
+        let err = run(&runner, &config, testing::release(&config, "app-staging")).unwrap_err();
+        assert_eq!(exit::code(&err), exit::GENERAL);
+        assert!(format!("{:#}", err).contains("aws: command not configured"));
+    }
+
+    #[test]
+    fn dry_run_stops_at_login() {
+        let config = testing::config();
+        let fake = FakeRunner::new()
+            .expect("aws", &LIST_PROFILES)
+            .stdout("sso\n")
+            .expect("aws", &VERIFY)
+            .fails(255, "The SSO session has expired");
+
+        let err = run(
+            &DryRun::new(&fake),
+            &config,
+            testing::release(&config, "app-staging"),
+        )
+        .unwrap_err();
+
+        assert_eq!(exit::code(&err), 0);
+        assert!(!exit::should_report(&err));
+    }
+
+    #[test]
+    fn checks_without_creating_a_profile_or_logging_in() {
+        let config = testing::config();
+        let release = testing::release(&config, "app-staging");
+        let runner = FakeRunner::new()
+            .expect("aws", &LIST_PROFILES)
+            .stdout("default\n")
+            .expect("aws", &LIST_PROFILES)
+            .stdout("sso\n")
+            .expect("aws", &VERIFY)
+            .fails(255, "The SSO session has expired")
+            .expect("aws", &LIST_PROFILES)
+            .stdout("sso\n")
+            .expect("aws", &VERIFY);
+
+        let missing = check(&runner, &config, release).unwrap();
+        let expired = check(&runner, &config, release).unwrap();
+        let valid = check(&runner, &config, release).unwrap();
+
+        assert_eq!(missing.as_deref(), Some("AWS profile sso is missing"));
+        assert_eq!(
+            expired.as_deref(),
+            Some("AWS profile sso has no valid SSO session")
+        );
+        assert_eq!(valid, None);
+    }
+}
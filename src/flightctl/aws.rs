@@ -1,16 +1,21 @@
+use super::runner::{self, Runner};
+use anyhow::Context;
 use log;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::process::{Command, ExitStatus, Output};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use tempfile::NamedTempFile;
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct EksCluster {
     pub endpoint: String,
     pub cert: String,
 }
 
-pub fn profile_exists(profile: &str) -> anyhow::Result<bool> {
-    let result = run_aws_cli(&["configure", "list-profiles"])?;
+pub fn profile_exists(runner: &dyn Runner, profile: &str) -> anyhow::Result<bool> {
+    let result = run_aws_cli(runner, &["configure", "list-profiles"])?;
     let output = String::from_utf8(result.stdout)?;
     Ok(output
         .lines()
@@ -18,80 +23,164 @@ pub fn profile_exists(profile: &str) -> anyhow::Result<bool> {
         .is_some())
 }
 
-pub fn create_profile(profile: &str, config: &HashMap<String, String>) -> anyhow::Result<()> {
+/// Setting that marks the profiles flightctl created, so it only ever
+/// removes those.
+const MANAGED: &str = "flightctl_managed";
+
+/// Creates the profile with the given settings, marked as flightctl's own.
+pub fn create_profile(
+    runner: &dyn Runner,
+    profile: &str,
+    config: &HashMap<String, String>,
+) -> anyhow::Result<()> {
     log::info!("Creating AWS profile: {}", profile);
 
-    for (key, value) in config.iter() {
-        run_aws_cli(&["--profile", profile, "configure", "set", key, value])?;
+    let mut settings: Vec<(&str, &str)> = config
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    settings.sort();
+    settings.push((MANAGED, "true"));
+    for (key, value) in settings {
+        runner::run_change(
+            runner,
+            "aws",
+            &["--profile", profile, "configure", "set", key, value],
+        )?;
     }
 
     Ok(())
 }
 
-pub fn verify_auth(profile: &str) -> anyhow::Result<()> {
-    run_aws_cli(&["--profile", profile, "sts", "get-caller-identity"]).and(Ok(()))
+pub fn verify_auth(runner: &dyn Runner, profile: &str) -> anyhow::Result<()> {
+    run_aws_cli(
+        runner,
+        &["--profile", profile, "sts", "get-caller-identity"],
+    )
+    .and(Ok(()))
 }
 
-pub fn sso_login(profile: &str) -> anyhow::Result<()> {
+/// Starts an SSO session for the profile. Returns whether the login ran,
+/// since dry runs only print it.
+pub fn sso_login(runner: &dyn Runner, profile: &str) -> anyhow::Result<bool> {
     log::info!("Logging in for AWS profile {}", profile);
-    let args = ["--profile", profile, "sso", "login"];
-    let mut child = aws_cli(&args).spawn()?;
-    let status = child.wait()?;
-    verify_exit(&args, status)
+    runner::run_interactive(runner, "aws", &["--profile", profile, "sso", "login"])
+}
+
+/// Whether flightctl created the profile.
+pub fn is_managed(runner: &dyn Runner, profile: &str) -> anyhow::Result<bool> {
+    // `configure get` fails when the setting isn't there.
+    let output = runner.output(
+        "aws",
+        &["--profile", profile, "configure", "get", MANAGED].map(String::from),
+    )?;
+    Ok(output.status.success() && String::from_utf8_lossy(&output.stdout).trim() == "true")
+}
+
+/// Ends every SSO session the AWS CLI has cached, along with the role
+/// credentials it cached for them, whatever profile they were started for.
+pub fn sso_logout(runner: &dyn Runner) -> anyhow::Result<()> {
+    log::info!("Logging out of every AWS SSO session");
+    runner::run_change(runner, "aws", &["sso", "logout"])
 }
 
-pub fn run_cli_print(args: &[&str]) -> anyhow::Result<()> {
-    let mut child = aws_cli(&args).spawn()?;
-    let status = child.wait()?;
-    verify_exit(&args, status)
+/// Removes a profile from the AWS CLI's config file, since `aws configure`
+/// can only add and change settings.
+pub fn delete_profile(runner: &dyn Runner, profile: &str) -> anyhow::Result<()> {
+    log::info!("Removing AWS profile: {}", profile);
+    let path = config_path()?;
+    let description = format!("Remove profile {} from {}", profile, path.display());
+    runner.change_in_process(&description, &mut || {
+        rewrite_config(&path, profile)
+            .with_context(|| format!("Failed to write AWS config {}", path.display()))
+    })
 }
 
-pub fn get_eks_cluster(profile: &str, region: &str, name: &str) -> anyhow::Result<EksCluster> {
-    let output = run_aws_cli(&[
-        "--profile",
-        profile,
-        "--region",
-        region,
-        "eks",
-        "describe-cluster",
-        "--name",
-        name,
-        "--query",
-        "cluster.{endpoint:endpoint,cert:certificateAuthority.data}",
-    ])?;
+pub fn run_cli_print(runner: &dyn Runner, args: &[&str]) -> anyhow::Result<()> {
+    runner::run_interactive(runner, "aws", args).map(|_| ())
+}
+
+pub fn get_eks_cluster(
+    runner: &dyn Runner,
+    profile: &str,
+    region: &str,
+    name: &str,
+) -> anyhow::Result<EksCluster> {
+    let output = run_aws_cli(
+        runner,
+        &[
+            "--profile",
+            profile,
+            "--region",
+            region,
+            "eks",
+            "describe-cluster",
+            "--name",
+            name,
+            "--query",
+            "cluster.{endpoint:endpoint,cert:certificateAuthority.data}",
+        ],
+    )?;
     let cluster = serde_yaml::from_slice(&output.stdout)?;
     Ok(cluster)
 }
 
-fn run_aws_cli(args: &[&str]) -> anyhow::Result<Output> {
-    let output = aws_cli(args).output()?;
-    match verify_exit(&args, output.status) {
-        Ok(_) => Ok(output),
-        Err(err) => {
-            Err(err.context(String::from_utf8(output.stderr).unwrap_or("(binary)".to_string())))
-        }
+fn config_path() -> anyhow::Result<PathBuf> {
+    match std::env::var_os("AWS_CONFIG_FILE") {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => Ok(super::home_dir()?.join(".aws").join("config")),
     }
 }
 
-fn aws_cli(args: &[&str]) -> Command {
-    log::debug!("Running AWS CLI with {:?}", args);
-    let mut command = Command::new("aws");
-    command.args(args);
-    command
+fn rewrite_config(path: &Path, profile: &str) -> anyhow::Result<()> {
+    let contents = std::fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut temp = NamedTempFile::new_in(dir)?;
+    temp.write_all(without_profile(&contents, profile).as_bytes())?;
+    temp.persist(path)?;
+    Ok(())
 }
 
-fn verify_exit(args: &[&str], status: ExitStatus) -> anyhow::Result<()> {
-    if status.success() {
-        Ok(())
+/// The config file's contents without the profile's section.
+fn without_profile(contents: &str, profile: &str) -> String {
+    let header = if profile == "default" {
+        String::from("[default]")
     } else {
-        let command: Vec<&str> = args.to_vec();
-        Err(anyhow::Error::msg(format!(
-            "aws {}: Command exited unsuccessfully (status code {})",
-            command.join(" "),
-            status
-                .code()
-                .map(|code| code.to_string())
-                .unwrap_or("unknown".to_string())
-        )))
+        format!("[profile {}]", profile)
+    };
+    let mut removing = false;
+    let mut result = String::new();
+    for line in contents.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            removing = trimmed.split_whitespace().collect::<Vec<&str>>().join(" ") == header;
+        }
+        if !removing {
+            result.push_str(line);
+        }
+    }
+    result
+}
+
+fn run_aws_cli(runner: &dyn Runner, args: &[&str]) -> anyhow::Result<Output> {
+    runner::run_get_output(runner, "aws", args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_only_the_profile_section() {
+        let contents = "[default]\nregion = us-east-1\n\n[profile sso]\nsso_region = us-east-1\nsso_start_url = https://example.awsapps.com/start\n\n[profile  other]\nregion = eu-west-1\n";
+
+        assert_eq!(
+            without_profile(contents, "sso"),
+            "[default]\nregion = us-east-1\n\n[profile  other]\nregion = eu-west-1\n"
+        );
+        assert_eq!(
+            without_profile(contents, "default"),
+            "[profile sso]\nsso_region = us-east-1\nsso_start_url = https://example.awsapps.com/start\n\n[profile  other]\nregion = eu-west-1\n"
+        );
     }
 }
@@ -0,0 +1,295 @@
+use super::aws::{self, EksCluster};
+use super::runner::Runner;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tempfile::NamedTempFile;
+
+/// How long a cluster's endpoint and certificate are used before they're
+/// fetched again.
+pub const TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Cluster details from `aws eks describe-cluster`, kept between commands so
+/// each one doesn't have to ask again. The cache is only an optimization:
+/// problems reading or writing it are logged and the details fetched anew.
+pub struct ClusterCache {
+    path: Option<PathBuf>,
+    refresh: bool,
+    read_only: bool,
+}
+
+/// Cached clusters by account, region and name.
+type Entries = BTreeMap<String, Entry>;
+
+#[derive(Deserialize, Serialize)]
+struct Entry {
+    cluster: EksCluster,
+    /// When the details were fetched, in seconds since the Unix epoch.
+    fetched_at: u64,
+}
+
+impl ClusterCache {
+    /// The user's cache, ignoring what's in it if refreshing.
+    pub fn new(refresh: bool) -> ClusterCache {
+        let path = match super::cache_dir() {
+            Ok(dir) => Some(dir.join("eks-clusters.json")),
+            Err(err) => {
+                log::debug!("Not caching cluster details: {}", err);
+                None
+            }
+        };
+        ClusterCache {
+            path,
+            refresh,
+            read_only: false,
+        }
+    }
+
+    /// Uses what's cached without storing anything new, for commands that
+    /// mustn't change anything.
+    pub fn read_only(self) -> ClusterCache {
+        ClusterCache {
+            read_only: true,
+            ..self
+        }
+    }
+
+    /// A cache that never has anything in it.
+    #[cfg(test)]
+    pub fn disabled() -> ClusterCache {
+        ClusterCache {
+            path: None,
+            refresh: false,
+            read_only: false,
+        }
+    }
+
+    /// A cache kept at the path, for tests.
+    #[cfg(test)]
+    pub fn at(path: PathBuf) -> ClusterCache {
+        ClusterCache {
+            path: Some(path),
+            refresh: false,
+            read_only: false,
+        }
+    }
+
+    /// The EKS cluster's details if they were ever cached, however long ago,
+    /// without asking AWS.
+    pub fn cached(&self, account: &str, region: &str, name: &str) -> Option<EksCluster> {
+        let path = self.path.as_ref()?;
+        read(path)
+            .remove(&key(account, region, name))
+            .map(|entry| entry.cluster)
+    }
+
+    /// The EKS cluster's details, from the cache if they were fetched
+    /// recently enough, or else from AWS as the profile.
+    pub fn eks_cluster(
+        &self,
+        runner: &dyn Runner,
+        account: &str,
+        profile: &str,
+        region: &str,
+        name: &str,
+    ) -> anyhow::Result<EksCluster> {
+        let key = key(account, region, name);
+        let path = match &self.path {
+            Some(path) => path,
+            None => return aws::get_eks_cluster(runner, profile, region, name),
+        };
+
+        let mut entries = read(path);
+        let now = now();
+        if !self.refresh {
+            if let Some(entry) = entries.get(&key) {
+                if now >= entry.fetched_at && now - entry.fetched_at < TTL.as_secs() {
+                    log::debug!("Using cached details for EKS cluster {}", key);
+                    return Ok(entry.cluster.clone());
+                }
+            }
+        }
+
+        let cluster = aws::get_eks_cluster(runner, profile, region, name)?;
+        if self.read_only {
+            return Ok(cluster);
+        }
+        let description = format!(
+            "Cache details for EKS cluster {} in {}",
+            key,
+            path.display()
+        );
+        entries.insert(
+            key,
+            Entry {
+                cluster: cluster.clone(),
+                fetched_at: now,
+            },
+        );
+        if let Err(err) = runner.change_in_process(&description, &mut || write(path, &entries)) {
+            log::warn!(
+                "Couldn't cache cluster details in {}: {}",
+                path.display(),
+                err
+            );
+        }
+        Ok(cluster)
+    }
+}
+
+fn key(account: &str, region: &str, name: &str) -> String {
+    format!("{}/{}/{}", account, region, name)
+}
+
+fn read(path: &Path) -> Entries {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            if err.kind() != ErrorKind::NotFound {
+                log::debug!("Couldn't read {}: {}", path.display(), err);
+            }
+            return Entries::new();
+        }
+    };
+    serde_json::from_str(&contents).unwrap_or_else(|err| {
+        log::debug!("Ignoring unreadable cache {}: {}", path.display(), err);
+        Entries::new()
+    })
+}
+
+/// Replaces the cache in one step, so a concurrent reader never sees half of
+/// it. A concurrent writer may win, which only costs another fetch later.
+fn write(path: &Path, entries: &Entries) -> anyhow::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+    let mut temp = NamedTempFile::new_in(dir)?;
+    temp.write_all(&serde_json::to_vec_pretty(entries)?)?;
+    temp.persist(path)?;
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flightctl::runner::DryRun;
+    use crate::flightctl::testing::FakeRunner;
+
+    const DESCRIBE: [&str; 10] = [
+        "--profile",
+        "sso",
+        "--region",
+        "us-east-1",
+        "eks",
+        "describe-cluster",
+        "--name",
+        "example",
+        "--query",
+        "cluster.{endpoint:endpoint,cert:certificateAuthority.data}",
+    ];
+
+    const CLUSTER: &str =
+        r#"{"endpoint": "https://example.eks.amazonaws.com", "cert": "Y2VydGlmaWNhdGU="}"#;
+
+    fn cache(dir: &Path, refresh: bool) -> ClusterCache {
+        ClusterCache {
+            path: Some(dir.join("eks-clusters.json")),
+            refresh,
+            read_only: false,
+        }
+    }
+
+    fn lookup(cache: &ClusterCache, runner: &FakeRunner, account: &str) -> EksCluster {
+        cache
+            .eks_cluster(runner, account, "sso", "us-east-1", "example")
+            .unwrap()
+    }
+
+    #[test]
+    fn fetches_once_until_expired() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), false);
+        let runner = FakeRunner::new().expect("aws", &DESCRIBE).stdout(CLUSTER);
+
+        let fetched = lookup(&cache, &runner, "123456789012");
+        let cached = lookup(&cache, &runner, "123456789012");
+
+        assert_eq!(fetched, cached);
+        assert_eq!(cached.endpoint, "https://example.eks.amazonaws.com");
+        assert_eq!(runner.calls().len(), 1);
+    }
+
+    #[test]
+    fn fetches_again_when_expired_refreshing_or_for_another_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut entries = Entries::new();
+        entries.insert(
+            String::from("123456789012/us-east-1/example"),
+            Entry {
+                cluster: EksCluster {
+                    endpoint: String::from("https://old.eks.amazonaws.com"),
+                    cert: String::new(),
+                },
+                fetched_at: now() - TTL.as_secs(),
+            },
+        );
+        write(&dir.path().join("eks-clusters.json"), &entries).unwrap();
+        let runner = FakeRunner::new()
+            .expect("aws", &DESCRIBE)
+            .stdout(CLUSTER)
+            .expect("aws", &DESCRIBE)
+            .stdout(CLUSTER)
+            .expect("aws", &DESCRIBE)
+            .stdout(CLUSTER);
+
+        let expired = lookup(&cache(dir.path(), false), &runner, "123456789012");
+        lookup(&cache(dir.path(), true), &runner, "123456789012");
+        lookup(&cache(dir.path(), false), &runner, "210987654321");
+
+        assert_eq!(expired.endpoint, "https://example.eks.amazonaws.com");
+        assert_eq!(read(&dir.path().join("eks-clusters.json")).len(), 2);
+    }
+
+    #[test]
+    fn ignores_an_unreadable_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("eks-clusters.json"), "not json").unwrap();
+        let runner = FakeRunner::new().expect("aws", &DESCRIBE).stdout(CLUSTER);
+
+        lookup(&cache(dir.path(), false), &runner, "123456789012");
+
+        assert_eq!(read(&dir.path().join("eks-clusters.json")).len(), 1);
+    }
+
+    #[test]
+    fn leaves_the_cache_alone_in_dry_runs_and_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let fake = FakeRunner::new()
+            .expect("aws", &DESCRIBE)
+            .stdout(CLUSTER)
+            .expect("aws", &DESCRIBE)
+            .stdout(CLUSTER);
+
+        cache(dir.path(), false)
+            .eks_cluster(
+                &DryRun::new(&fake),
+                "123456789012",
+                "sso",
+                "us-east-1",
+                "example",
+            )
+            .unwrap();
+        lookup(&cache(dir.path(), false).read_only(), &fake, "123456789012");
+
+        assert!(!dir.path().join("eks-clusters.json").exists());
+    }
+}
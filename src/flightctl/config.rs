@@ -1,3 +1,4 @@
+use super::kubeconfig::{KubeconfigFile, KubeconfigMode};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fmt;
@@ -73,6 +74,14 @@ pub enum ApplicationConfig {
 
         #[serde(default)]
         selector: HashMap<String, String>,
+
+        /// ConfigMap that `config set` stores new variables in.
+        #[serde(default, rename = "configMap")]
+        config_map: Option<String>,
+
+        /// Secret that `config set --secret` stores new variables in.
+        #[serde(default)]
+        secret: Option<String>,
     },
 }
 
@@ -88,8 +97,8 @@ pub enum Console {
 
 #[derive(Debug, Deserialize)]
 pub struct ApplicationManifests {
-    provider: ManifestsProvider,
-    repo: String,
+    pub provider: ManifestsProvider,
+    pub repo: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -109,6 +118,9 @@ pub struct Release {
     pub manifests: ManifestConfig,
 
     pub name: String,
+
+    #[serde(default)]
+    pub sensitive: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -145,8 +157,14 @@ pub struct Config {
     pub auth: Vec<Auth>,
     pub clusters: Vec<Cluster>,
     pub contexts: Vec<Context>,
+    #[serde(default)]
+    pub kubeconfig: KubeconfigMode,
     pub kind: String,
     pub releases: Vec<Release>,
+
+    /// The directory the configuration was loaded from.
+    #[serde(skip)]
+    pub root: PathBuf,
 }
 
 impl Config {
@@ -180,6 +198,11 @@ impl Config {
             )))
     }
 
+    /// The kubeconfig this workspace's contexts live in.
+    pub fn kubeconfig_file(&self) -> KubeconfigFile {
+        KubeconfigFile::new(self.kubeconfig, &self.root)
+    }
+
     pub fn find_context(&self, release: &Release) -> anyhow::Result<&Context> {
         self.contexts
             .iter()
@@ -195,6 +218,7 @@ impl Config {
 pub struct ConfigFile {
     pub config: Config,
     pub path: PathBuf,
+    pub source: String,
 }
 
 impl ConfigFile {
@@ -202,12 +226,13 @@ impl ConfigFile {
         let current_dir = std::env::current_dir()?;
         match find_config(current_dir) {
             Some(path) => {
-                let file = std::fs::File::open(&path)?;
-                let reader = std::io::BufReader::new(file);
-                let config = serde_yaml::from_reader(reader)?;
+                let source = std::fs::read_to_string(&path)?;
+                let mut config: Config = serde_yaml::from_str(&source)?;
+                config.root = path.parent().map(PathBuf::from).unwrap_or_default();
                 Ok(ConfigFile {
                     config: config,
                     path: path,
+                    source,
                 })
             }
             None => Err(anyhow::Error::msg("No configuration file found")),
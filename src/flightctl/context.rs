@@ -1,117 +1,271 @@
-use super::aws;
+use super::cluster_cache::ClusterCache;
 use super::config::{Auth, AuthConfig, Cluster, ClusterConfig, Config, Context, Release};
-use super::kubeconfig_writer;
-use kube::config::{
-    AuthInfo, ExecConfig, Kubeconfig, KubeconfigError, NamedAuthInfo, NamedCluster,
-};
+use super::kubeconfig::KubeconfigFile;
+use super::kubeconfig_writer::{self, Entries};
+use super::runner::Runner;
+use kube::config::{AuthInfo, ExecConfig, Kubeconfig, NamedAuthInfo, NamedCluster, NamedContext};
 use std::collections::HashMap;
+use std::fmt;
+use std::hash::{DefaultHasher, Hash, Hasher};
 
-pub fn prepare(config: &Config, release: &Release) -> anyhow::Result<()> {
-    let context = config.find_context(&release)?;
-    let auth = config.find_auth(context)?;
-    let cluster = config.find_cluster(context)?;
-    let kubeauth = build_auth(context, auth, cluster);
-    let kubecluster = build_cluster(cluster, auth)?;
+/// A way a kubeconfig entry differs from what the workspace configures.
+#[derive(Debug, PartialEq)]
+pub enum Drift {
+    /// The entry, such as `user staging`, isn't in the kubeconfig.
+    Missing { entry: String },
+
+    /// One of the entry's fields has a different value.
+    Changed {
+        entry: String,
+        field: &'static str,
+        actual: String,
+        expected: String,
+    },
+}
 
-    let kubeconfig = read_kubeconfig()?;
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Drift::Missing { entry } => write!(f, "{} is missing", entry),
+            Drift::Changed {
+                entry,
+                field,
+                actual,
+                expected,
+            } => write!(
+                f,
+                "{} has {} {}, expected {}",
+                entry, field, actual, expected
+            ),
+        }
+    }
+}
+
+pub fn prepare(
+    runner: &dyn Runner,
+    cache: &ClusterCache,
+    config: &Config,
+    release: &Release,
+) -> anyhow::Result<()> {
+    for drift in sync(runner, cache, config, release, false)? {
+        log::info!("Updating Kubernetes configuration: {}", drift);
+    }
+    Ok(())
+}
+
+/// Compares the release's context, its credentials and its cluster with the
+/// Kubernetes configuration, and writes whichever drifted unless only
+/// checking. Returns the drift found.
+pub fn sync(
+    runner: &dyn Runner,
+    cache: &ClusterCache,
+    config: &Config,
+    release: &Release,
+    check: bool,
+) -> anyhow::Result<Vec<Drift>> {
+    let kubeconfig = config.kubeconfig_file().read()?;
     log::debug!(
         "Loaded Kubernetes configuration successfully: {:?}",
         kubeconfig
     );
-    ensure_auth(&kubeconfig, kubeauth)?;
-    ensure_cluster(&kubeconfig, kubecluster)?;
-    ensure_context(&kubeconfig, &context)
+    update(runner, cache, &kubeconfig, config, release, check)
 }
 
-fn read_kubeconfig() -> anyhow::Result<Kubeconfig> {
-    log::debug!("Loading Kubernetes configuration");
-    match Kubeconfig::read() {
-        Ok(config) => anyhow::Ok(config),
-        Err(KubeconfigError::ReadConfig { .. }) => anyhow::Ok(empty_config()),
-        Err(err) => Err(anyhow::anyhow!(err)),
+fn update(
+    runner: &dyn Runner,
+    cache: &ClusterCache,
+    kubeconfig: &Kubeconfig,
+    config: &Config,
+    release: &Release,
+    check: bool,
+) -> anyhow::Result<Vec<Drift>> {
+    let file = config.kubeconfig_file();
+    let context = config.find_context(&release)?;
+    let auth = config.find_auth(context)?;
+    let cluster = config.find_cluster(context)?;
+    let mut kubeauth = build_auth(context, auth, cluster);
+    kubeauth.name = file.name(&kubeauth.name);
+    let mut kubecluster = build_cluster(runner, cache, cluster, auth)?;
+    kubecluster.name = file.name(&kubecluster.name);
+
+    let mut plan = Plan::default();
+    ensure_auth(kubeconfig, kubeauth, &mut plan);
+    ensure_cluster(kubeconfig, kubecluster, &mut plan);
+    ensure_context(&file, kubeconfig, context, &mut plan);
+    if !check {
+        kubeconfig_writer::write(runner, &file, plan.entries)?;
     }
+    Ok(plan.drift)
+}
+
+/// The drift found so far, and the entries to write to repair it.
+#[derive(Default)]
+struct Plan {
+    drift: Vec<Drift>,
+    entries: Entries,
 }
 
-fn empty_config() -> Kubeconfig {
-    Kubeconfig {
-        auth_infos: vec![],
-        kind: None,
-        api_version: None,
-        clusters: vec![],
-        contexts: vec![],
-        current_context: None,
-        extensions: None,
-        preferences: None,
+impl Plan {
+    /// Records drift if the entry is missing, returning the entry otherwise.
+    fn find<'k, T>(&mut self, entry: &str, actual: Option<&'k T>) -> Option<&'k T> {
+        if actual.is_none() {
+            self.drift.push(Drift::Missing {
+                entry: String::from(entry),
+            });
+        }
+        actual
+    }
+
+    /// Records drift if the field's value differs from what's expected.
+    fn compare<T: PartialEq>(
+        &mut self,
+        entry: &str,
+        field: &'static str,
+        actual: T,
+        expected: T,
+        show: fn(T) -> String,
+    ) {
+        if actual != expected {
+            self.drift.push(Drift::Changed {
+                entry: String::from(entry),
+                field,
+                actual: show(actual),
+                expected: show(expected),
+            });
+        }
+    }
+
+    /// Whether any drift has been recorded since the count was taken.
+    fn drifted_since(&self, count: usize) -> bool {
+        self.drift.len() > count
     }
 }
 
-fn ensure_context(config: &Kubeconfig, expected: &Context) -> anyhow::Result<()> {
-    log::debug!("Checking Kubernetes context {}", &expected.name);
+fn ensure_context(file: &KubeconfigFile, config: &Kubeconfig, expected: &Context, plan: &mut Plan) {
+    let name = file.name(&expected.name);
+    let cluster = file.name(&expected.cluster);
+    let entry = format!("context {}", name);
+    log::debug!("Checking Kubernetes context {}", &name);
 
-    let exists = config
-        .contexts
-        .iter()
-        .find(|actual| {
-            &actual.name == &expected.name
-                && match &actual.context {
-                    Some(context) => {
-                        &context.cluster == &expected.cluster
-                            && &context.user == &expected.name
-                            && context.namespace.as_deref() == Some(&expected.namespace)
-                    }
-                    None => false,
-                }
-        })
-        .is_some();
+    let count = plan.drift.len();
+    let actual = config.contexts.iter().find(|actual| actual.name == name);
+    if let Some(actual) = plan.find(&entry, actual) {
+        let context = actual.context.as_ref();
+        plan.compare(
+            &entry,
+            "cluster",
+            context.map(|context| context.cluster.as_str()),
+            Some(cluster.as_str()),
+            show,
+        );
+        plan.compare(
+            &entry,
+            "user",
+            context.map(|context| context.user.as_str()),
+            Some(name.as_str()),
+            show,
+        );
+        plan.compare(
+            &entry,
+            "namespace",
+            context.and_then(|context| context.namespace.as_deref()),
+            Some(expected.namespace.as_str()),
+            show,
+        );
+    }
 
-    if exists {
-        log::debug!("Using existing Kubenetes context");
-        Ok(())
+    if plan.drifted_since(count) {
+        plan.entries.contexts.push(NamedContext {
+            name: name.clone(),
+            context: Some(kube::config::Context {
+                cluster,
+                user: name,
+                namespace: Some(expected.namespace.clone()),
+                extensions: None,
+            }),
+        });
     } else {
-        log::info!("Writing Kubernetes context: {}", expected.name);
-        kubeconfig_writer::write_context(
-            &expected.name,
-            &expected.name,
-            &expected.cluster,
-            &expected.namespace,
-        )
+        log::debug!("Using existing Kubenetes context");
     }
 }
 
-fn ensure_auth(config: &Kubeconfig, expected: NamedAuthInfo) -> anyhow::Result<()> {
+fn ensure_auth(config: &Kubeconfig, expected: NamedAuthInfo, plan: &mut Plan) {
+    let entry = format!("user {}", expected.name);
     log::debug!("Checking Kubernetes credentials for {}", &expected.name);
 
-    let exists =
-        config
-            .auth_infos
-            .iter()
-            .find(|actual| {
-                expected.name == actual.name
-                    && if let (Some(expected_auth_info), Some(actual_auth_info)) =
-                        (&expected.auth_info, &actual.auth_info)
-                    {
-                        expected_auth_info.client_certificate_data
-                            == actual_auth_info.client_certificate_data
-                            && expected_auth_info.exec.as_ref().map(|exec| {
-                                (&exec.api_version, &exec.args, &exec.command, &exec.args)
-                            }) == actual_auth_info.exec.as_ref().map(|exec| {
-                                (&exec.api_version, &exec.args, &exec.command, &exec.args)
-                            })
-                    } else {
-                        false
-                    }
-            })
-            .is_some();
+    let count = plan.drift.len();
+    let actual = config
+        .auth_infos
+        .iter()
+        .find(|actual| actual.name == expected.name);
+    if let Some(actual) = plan.find(&entry, actual) {
+        let actual = actual.auth_info.as_ref();
+        let expected = expected.auth_info.as_ref();
+        plan.compare(
+            &entry,
+            "client-certificate-data",
+            actual.and_then(|auth| auth.client_certificate_data.as_deref()),
+            expected.and_then(|auth| auth.client_certificate_data.as_deref()),
+            show_data,
+        );
 
-    if exists {
-        log::debug!("Using existing Kubernetes credentials");
-        Ok(())
+        match (
+            actual.and_then(|auth| auth.exec.as_ref()),
+            expected.and_then(|auth| auth.exec.as_ref()),
+        ) {
+            (None, Some(expected)) => plan.drift.push(Drift::Changed {
+                entry: entry.clone(),
+                field: "exec",
+                actual: String::from("(none)"),
+                expected: show_exec(expected),
+            }),
+            (actual, expected) => compare_exec(plan, &entry, actual, expected),
+        }
+    }
+
+    if plan.drifted_since(count) {
+        plan.entries.auth_infos.push(expected);
     } else {
-        log::info!("Writing Kubernetes credentials for {}", &expected.name);
-        kubeconfig_writer::write_auth(expected)
+        log::debug!("Using existing Kubernetes credentials");
     }
 }
 
+fn compare_exec(
+    plan: &mut Plan,
+    entry: &str,
+    actual: Option<&ExecConfig>,
+    expected: Option<&ExecConfig>,
+) {
+    plan.compare(
+        entry,
+        "exec.apiVersion",
+        actual.and_then(|exec| exec.api_version.as_deref()),
+        expected.and_then(|exec| exec.api_version.as_deref()),
+        show,
+    );
+    plan.compare(
+        entry,
+        "exec.command",
+        actual.and_then(|exec| exec.command.as_deref()),
+        expected.and_then(|exec| exec.command.as_deref()),
+        show,
+    );
+    plan.compare(
+        entry,
+        "exec.args",
+        actual.and_then(|exec| exec.args.as_ref()),
+        expected.and_then(|exec| exec.args.as_ref()),
+        show_args,
+    );
+    plan.compare(
+        entry,
+        "exec.env",
+        actual.and_then(|exec| exec.env.as_ref()),
+        expected.and_then(|exec| exec.env.as_ref()),
+        show_env,
+    );
+}
+
 fn build_auth(context: &Context, auth: &Auth, cluster: &Cluster) -> NamedAuthInfo {
     let mut result = AuthInfo {
         auth_provider: None,
@@ -168,36 +322,108 @@ fn build_auth(context: &Context, auth: &Auth, cluster: &Cluster) -> NamedAuthInf
     }
 }
 
-fn ensure_cluster(config: &Kubeconfig, expected: NamedCluster) -> anyhow::Result<()> {
+fn ensure_cluster(config: &Kubeconfig, expected: NamedCluster, plan: &mut Plan) {
+    let entry = format!("cluster {}", expected.name);
     log::debug!("Checking for Kubernetes cluster");
 
-    let exists = config
+    let count = plan.drift.len();
+    let actual = config
         .clusters
         .iter()
-        .find(|actual| {
-            &actual.name == &expected.name
-                && if let (Some(actual_cluster), Some(expected_cluster)) =
-                    (&actual.cluster, &expected.cluster)
-                {
-                    actual_cluster.server == expected_cluster.server
-                        && actual_cluster.certificate_authority_data
-                            == expected_cluster.certificate_authority_data
-                } else {
-                    false
-                }
-        })
-        .is_some();
+        .find(|actual| actual.name == expected.name);
+    if let Some(actual) = plan.find(&entry, actual) {
+        let actual = actual.cluster.as_ref();
+        let expected = expected.cluster.as_ref();
+        plan.compare(
+            &entry,
+            "server",
+            actual.and_then(|cluster| cluster.server.as_deref()),
+            expected.and_then(|cluster| cluster.server.as_deref()),
+            show,
+        );
+        plan.compare(
+            &entry,
+            "certificate-authority-data",
+            actual.and_then(|cluster| cluster.certificate_authority_data.as_deref()),
+            expected.and_then(|cluster| cluster.certificate_authority_data.as_deref()),
+            show_data,
+        );
+    }
 
-    if exists {
-        log::debug!("Cluster already configured");
-        Ok(())
+    if plan.drifted_since(count) {
+        plan.entries.clusters.push(expected);
     } else {
-        log::info!("Writing cluster {}", &expected.name);
-        kubeconfig_writer::write_cluster(expected)
+        log::debug!("Cluster already configured");
+    }
+}
+
+fn show(value: Option<&str>) -> String {
+    match value {
+        Some(value) => format!("{:?}", value),
+        None => String::from("(none)"),
+    }
+}
+
+fn show_args(args: Option<&Vec<String>>) -> String {
+    match args {
+        Some(args) => format!("{:?}", args),
+        None => String::from("(none)"),
+    }
+}
+
+/// Shows an exec plugin as the command it runs.
+fn show_exec(exec: &ExecConfig) -> String {
+    let mut words: Vec<&str> = exec.command.as_deref().into_iter().collect();
+    words.extend(exec.args.iter().flatten().map(String::as_str));
+    format!("{:?}", words.join(" "))
+}
+
+/// Shows environment variables as `NAME=value`, in order.
+fn show_env(env: Option<&Vec<HashMap<String, String>>>) -> String {
+    let vars = match env {
+        Some(vars) => vars,
+        None => return String::from("(none)"),
+    };
+    let vars: Vec<String> = vars
+        .iter()
+        .map(|var| match (var.get("name"), var.get("value")) {
+            (Some(name), Some(value)) => format!("{}={}", name, value),
+            _ => {
+                let mut fields: Vec<String> = var
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect();
+                fields.sort();
+                format!("{{{}}}", fields.join(", "))
+            }
+        })
+        .collect();
+    format!("[{}]", vars.join(", "))
+}
+
+/// Shows base64 data, such as a certificate, by its size and a fingerprint,
+/// since the data itself is too long to compare by eye.
+fn show_data(data: Option<&str>) -> String {
+    match data {
+        Some(data) => {
+            let mut hasher = DefaultHasher::new();
+            data.hash(&mut hasher);
+            format!(
+                "({} bytes, fingerprint {:016x})",
+                data.len(),
+                hasher.finish()
+            )
+        }
+        None => String::from("(none)"),
     }
 }
 
-fn build_cluster(cluster: &Cluster, auth: &Auth) -> anyhow::Result<NamedCluster> {
+fn build_cluster(
+    runner: &dyn Runner,
+    cache: &ClusterCache,
+    cluster: &Cluster,
+    auth: &Auth,
+) -> anyhow::Result<NamedCluster> {
     match &cluster.config {
         ClusterConfig::Eks { name, region } => {
             log::debug!(
@@ -206,7 +432,7 @@ fn build_cluster(cluster: &Cluster, auth: &Auth) -> anyhow::Result<NamedCluster>
                 auth.name,
                 region
             );
-            let eks_cluster = aws::get_eks_cluster(&auth.name, region, name)?;
+            let eks_cluster = cache.eks_cluster(runner, account(auth), &auth.name, region, name)?;
             Ok(NamedCluster {
                 name: cluster.name.clone(),
                 cluster: Some(kube::config::Cluster {
@@ -222,3 +448,292 @@ fn build_cluster(cluster: &Cluster, auth: &Auth) -> anyhow::Result<NamedCluster>
         }
     }
 }
+
+/// The AWS account the credentials sign in to. Without an account ID, the
+/// profile stands in for the account.
+fn account(auth: &Auth) -> &str {
+    let AuthConfig::AwsSso { config } = &auth.config;
+    config
+        .get("sso_account_id")
+        .map(String::as_str)
+        .unwrap_or(&auth.name)
+}
+
+/// Whether a kubeconfig user is one `prepare` wrote for the credentials: it
+/// gets its token from `aws eks get-token` as their AWS profile.
+pub fn is_own_user(entry: &NamedAuthInfo, auth: &Auth) -> bool {
+    let exec = match entry.auth_info.as_ref().and_then(|info| info.exec.as_ref()) {
+        Some(exec) => exec,
+        None => return false,
+    };
+    let args = exec.args.as_deref().unwrap_or_default();
+    exec.command.as_deref() == Some("aws")
+        && args.windows(2).any(|pair| pair == ["eks", "get-token"])
+        && exec.env.iter().flatten().any(|var| {
+            var.get("name").map(String::as_str) == Some("AWS_PROFILE")
+                && var.get("value") == Some(&auth.name)
+        })
+}
+
+/// Whether a kubeconfig cluster is one `prepare` wrote for the cluster: its
+/// server is the EKS cluster's cached endpoint.
+pub fn is_own_cluster(
+    cache: &ClusterCache,
+    entry: &NamedCluster,
+    cluster: &Cluster,
+    auth: &Auth,
+) -> bool {
+    let server = entry
+        .cluster
+        .as_ref()
+        .and_then(|cluster| cluster.server.as_deref());
+    match &cluster.config {
+        ClusterConfig::Eks { name, region } => cache
+            .cached(account(auth), region, name)
+            .is_some_and(|eks_cluster| server == Some(eks_cluster.endpoint.as_str())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flightctl::kubeconfig::KubeconfigMode;
+    use crate::flightctl::testing::{self, FakeRunner};
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    const DESCRIBE: [&str; 10] = [
+        "--profile",
+        "sso",
+        "--region",
+        "us-east-1",
+        "eks",
+        "describe-cluster",
+        "--name",
+        "example",
+        "--query",
+        "cluster.{endpoint:endpoint,cert:certificateAuthority.data}",
+    ];
+
+    const CLUSTER: &str =
+        r#"{"endpoint": "https://example.eks.amazonaws.com", "cert": "Y2VydGlmaWNhdGU="}"#;
+
+    const KUBECONFIG: &str = r#"
+apiVersion: v1
+kind: Config
+clusters:
+- name: acme-cluster
+  cluster:
+    server: https://example.eks.amazonaws.com
+    certificate-authority-data: Y2VydGlmaWNhdGU=
+users:
+- name: acme-staging
+  user:
+    exec:
+      apiVersion: client.authentication.k8s.io/v1beta1
+      command: aws
+      args: [--region, us-east-1, eks, get-token, --cluster-name, example]
+      env:
+      - name: AWS_PROFILE
+        value: sso
+contexts:
+- name: acme-staging
+  context:
+    cluster: acme-cluster
+    user: acme-staging
+    namespace: app-staging
+"#;
+
+    /// The fixture as a workspace named acme keeping its own kubeconfig in a
+    /// temporary directory, so nothing is written to the real one.
+    fn workspace() -> (TempDir, Config, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = testing::config();
+        config.kubeconfig = KubeconfigMode::Workspace;
+        config.root = dir.path().join("acme");
+        let path = config.kubeconfig_file().write_path().unwrap();
+        (dir, config, path)
+    }
+
+    #[test]
+    fn writes_missing_context() {
+        let (_dir, config, path) = workspace();
+        let runner = FakeRunner::new().expect("aws", &DESCRIBE).stdout(CLUSTER);
+
+        update(
+            &runner,
+            &ClusterCache::disabled(),
+            &Kubeconfig::default(),
+            &config,
+            testing::release(&config, "app-staging"),
+            false,
+        )
+        .unwrap();
+
+        let written = Kubeconfig::read_from(&path).unwrap();
+        let expected = Kubeconfig::from_yaml(KUBECONFIG).unwrap();
+        assert_eq!(
+            serde_yaml::to_value(written).unwrap(),
+            serde_yaml::to_value(expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn keeps_current_context() {
+        let (_dir, config, path) = workspace();
+        let runner = FakeRunner::new().expect("aws", &DESCRIBE).stdout(CLUSTER);
+
+        let drift = update(
+            &runner,
+            &ClusterCache::disabled(),
+            &Kubeconfig::from_yaml(KUBECONFIG).unwrap(),
+            &config,
+            testing::release(&config, "app-staging"),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(drift, vec![]);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn rewrites_changed_cluster() {
+        let (_dir, config, path) = workspace();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, KUBECONFIG).unwrap();
+        let runner = FakeRunner::new().expect("aws", &DESCRIBE).stdout(
+            r#"{"endpoint": "https://moved.eks.amazonaws.com", "cert": "Y2VydGlmaWNhdGU="}"#,
+        );
+
+        let drift = update(
+            &runner,
+            &ClusterCache::disabled(),
+            &Kubeconfig::from_yaml(KUBECONFIG).unwrap(),
+            &config,
+            testing::release(&config, "app-staging"),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            drift,
+            vec![Drift::Changed {
+                entry: String::from("cluster acme-cluster"),
+                field: "server",
+                actual: String::from(r#""https://example.eks.amazonaws.com""#),
+                expected: String::from(r#""https://moved.eks.amazonaws.com""#),
+            }]
+        );
+        let written = Kubeconfig::read_from(&path).unwrap();
+        assert_eq!(
+            written.clusters[0]
+                .cluster
+                .as_ref()
+                .unwrap()
+                .server
+                .as_deref(),
+            Some("https://moved.eks.amazonaws.com")
+        );
+        assert_eq!(written.auth_infos.len(), 1);
+        assert_eq!(written.contexts.len(), 1);
+    }
+
+    #[test]
+    fn rewrites_credentials_for_another_profile() {
+        let (_dir, config, path) = workspace();
+        let kubeconfig = KUBECONFIG.replace("value: sso", "value: old");
+        let runner = FakeRunner::new().expect("aws", &DESCRIBE).stdout(CLUSTER);
+
+        let drift = update(
+            &runner,
+            &ClusterCache::disabled(),
+            &Kubeconfig::from_yaml(&kubeconfig).unwrap(),
+            &config,
+            testing::release(&config, "app-staging"),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            drift,
+            vec![Drift::Changed {
+                entry: String::from("user acme-staging"),
+                field: "exec.env",
+                actual: String::from("[AWS_PROFILE=old]"),
+                expected: String::from("[AWS_PROFILE=sso]"),
+            }]
+        );
+        let written = Kubeconfig::read_from(&path).unwrap();
+        assert_eq!(written.auth_infos.len(), 1);
+        assert!(written.clusters.is_empty());
+    }
+
+    #[test]
+    fn checks_without_writing() {
+        let (_dir, config, path) = workspace();
+        let kubeconfig = KUBECONFIG.replace("namespace: app-staging", "namespace: other");
+        let runner = FakeRunner::new().expect("aws", &DESCRIBE).stdout(CLUSTER);
+
+        let drift = update(
+            &runner,
+            &ClusterCache::disabled(),
+            &Kubeconfig::from_yaml(&kubeconfig).unwrap(),
+            &config,
+            testing::release(&config, "app-staging"),
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(
+            drift
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>(),
+            vec![r#"context acme-staging has namespace "other", expected "app-staging""#]
+        );
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn ignores_entries_without_the_workspace_prefix() {
+        let (_dir, config, path) = workspace();
+        let runner = FakeRunner::new().expect("aws", &DESCRIBE).stdout(CLUSTER);
+
+        // The unprefixed entries belong to the user's kubeconfig, so they
+        // don't count as this workspace's.
+        update(
+            &runner,
+            &ClusterCache::disabled(),
+            &Kubeconfig::from_yaml(&KUBECONFIG.replace("acme-", "")).unwrap(),
+            &config,
+            testing::release(&config, "app-staging"),
+            false,
+        )
+        .unwrap();
+
+        let written = Kubeconfig::read_from(&path).unwrap();
+        assert_eq!(written.contexts[0].name, "acme-staging");
+    }
+
+    #[test]
+    fn fails_without_cluster_details() {
+        let (_dir, config, path) = workspace();
+        let runner = FakeRunner::new().expect("aws", &DESCRIBE).fails(
+            254,
+            "ResourceNotFoundException: No cluster found for name: example",
+        );
+
+        let err = update(
+            &runner,
+            &ClusterCache::disabled(),
+            &Kubeconfig::default(),
+            &config,
+            testing::release(&config, "app-staging"),
+            false,
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("No cluster found"));
+        assert!(!path.exists());
+    }
+}
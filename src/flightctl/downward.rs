@@ -0,0 +1,229 @@
+use k8s_openapi::api::core::v1 as k8s;
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+
+/// Evaluates a downward API `fieldRef` path against a pod, the same way the
+/// kubelet does when populating a container's environment.
+pub fn field_value(pod: &k8s::Pod, path: &str) -> Option<String> {
+    let metadata = &pod.metadata;
+    let spec = pod.spec.as_ref();
+    let status = pod.status.as_ref();
+
+    if let Some(key) = subscript(path, "metadata.labels") {
+        return metadata.labels.as_ref()?.get(key).cloned();
+    }
+
+    if let Some(key) = subscript(path, "metadata.annotations") {
+        return metadata.annotations.as_ref()?.get(key).cloned();
+    }
+
+    match path {
+        "metadata.name" => metadata.name.clone(),
+        "metadata.namespace" => metadata.namespace.clone(),
+        "metadata.uid" => metadata.uid.clone(),
+        "metadata.labels" => metadata.labels.as_ref().map(format_map),
+        "metadata.annotations" => metadata.annotations.as_ref().map(format_map),
+        "spec.nodeName" => spec?.node_name.clone(),
+        "spec.serviceAccountName" => spec?.service_account_name.clone(),
+        "status.hostIP" => status?.host_ip.clone(),
+        "status.podIP" => status?.pod_ip.clone(),
+        "status.podIPs" => status?.pod_ips.as_ref().map(|ips| {
+            ips.iter()
+                .filter_map(|ip| ip.ip.clone())
+                .collect::<Vec<String>>()
+                .join(",")
+        }),
+        _ => None,
+    }
+}
+
+/// Evaluates a `resourceFieldRef` for the named container, rounding up to
+/// a whole number of the divisor as Kubernetes does.
+pub fn resource_value(
+    pod: &k8s::Pod,
+    container: &str,
+    resource_field_ref: &k8s::ResourceFieldSelector,
+) -> Option<String> {
+    let spec = pod.spec.as_ref()?;
+    let container_name = resource_field_ref
+        .container_name
+        .as_deref()
+        .unwrap_or(container);
+    let resources = spec
+        .containers
+        .iter()
+        .chain(spec.init_containers.iter().flatten())
+        .find(|candidate| candidate.name == container_name)?
+        .resources
+        .as_ref()?;
+
+    let (kind, resource) = resource_field_ref.resource.split_once('.')?;
+    let quantities = match kind {
+        "limits" => resources.limits.as_ref(),
+        "requests" => resources.requests.as_ref(),
+        _ => None,
+    }?;
+    let Quantity(quantity) = quantities.get(resource)?;
+    let value = parse_quantity(quantity)?;
+
+    let divisor = match &resource_field_ref.divisor {
+        Some(Quantity(divisor)) => parse_quantity(divisor)?,
+        None => NANO,
+    };
+    if divisor <= 0 {
+        return None;
+    }
+
+    Some(((value + divisor - 1) / divisor).to_string())
+}
+
+const NANO: i128 = 1_000_000_000;
+
+/// Parses a Kubernetes quantity such as `500m`, `512Mi` or `1e3` into
+/// billionths of a unit, which is precise enough for every suffix.
+fn parse_quantity(quantity: &str) -> Option<i128> {
+    let quantity = quantity.trim();
+    let split = quantity
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '+' || c == '-'))
+        .unwrap_or(quantity.len());
+    let (number, suffix) = quantity.split_at(split);
+
+    let (multiplier, divisor): (i128, i128) = match suffix {
+        "n" => (1, 1),
+        "u" => (1_000, 1),
+        "m" => (1_000_000, 1),
+        "" => (NANO, 1),
+        "k" => (NANO * 1_000, 1),
+        "M" => (NANO * 1_000_000, 1),
+        "G" => (NANO * 1_000_000_000, 1),
+        "T" => (NANO * 1_000_000_000_000, 1),
+        "P" => (NANO * 1_000_000_000_000_000, 1),
+        "E" => (NANO * 1_000_000_000_000_000_000, 1),
+        "Ki" => (NANO << 10, 1),
+        "Mi" => (NANO << 20, 1),
+        "Gi" => (NANO << 30, 1),
+        "Ti" => (NANO << 40, 1),
+        "Pi" => (NANO << 50, 1),
+        "Ei" => (NANO << 60, 1),
+        exponent if exponent.starts_with(['e', 'E']) => {
+            let exponent: i32 = exponent[1..].parse().ok()?;
+            if exponent >= 0 {
+                (NANO * 10i128.checked_pow(exponent as u32)?, 1)
+            } else {
+                (NANO, 10i128.checked_pow(exponent.unsigned_abs())?)
+            }
+        }
+        _ => return None,
+    };
+
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let negative = whole.starts_with('-');
+    let whole: i128 = match whole.trim_start_matches(['+', '-']) {
+        "" => 0,
+        digits => digits.parse().ok()?,
+    };
+    let mut value = whole.checked_mul(multiplier)?;
+    let mut scale = multiplier;
+    for digit in fraction.chars() {
+        scale /= 10;
+        value += i128::from(digit.to_digit(10)?) * scale;
+    }
+    value /= divisor;
+
+    Some(if negative { -value } else { value })
+}
+
+fn subscript<'a>(path: &'a str, field: &str) -> Option<&'a str> {
+    let key = path.strip_prefix(field)?.strip_prefix("['")?;
+    key.strip_suffix("']")
+}
+
+fn format_map(map: &std::collections::BTreeMap<String, String>) -> String {
+    map.iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key, value))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_quantities_in_billionths() {
+        assert_eq!(parse_quantity("1"), Some(NANO));
+        assert_eq!(parse_quantity("500m"), Some(NANO / 2));
+        assert_eq!(parse_quantity("250u"), Some(250_000));
+        assert_eq!(parse_quantity("2k"), Some(2_000 * NANO));
+        assert_eq!(parse_quantity("512Mi"), Some(512 * 1_048_576 * NANO));
+        assert_eq!(parse_quantity("1.5Gi"), Some(1_610_612_736 * NANO));
+        assert_eq!(parse_quantity("1e3"), Some(1_000 * NANO));
+        assert_eq!(parse_quantity("1e-3"), Some(NANO / 1_000));
+        assert_eq!(parse_quantity("-1"), Some(-NANO));
+        assert_eq!(parse_quantity("1Xi"), None);
+        assert_eq!(parse_quantity("1.2.3"), None);
+    }
+
+    fn pod() -> k8s::Pod {
+        serde_json::from_value(json!({
+            "metadata": {},
+            "spec": {
+                "containers": [{
+                    "name": "main",
+                    "resources": {
+                        "limits": { "cpu": "1500m", "memory": "512Mi" },
+                        "requests": { "cpu": "250m" },
+                    },
+                }],
+                "initContainers": [{
+                    "name": "migrate",
+                    "resources": { "limits": { "memory": "1Gi" } },
+                }],
+            },
+        }))
+        .unwrap()
+    }
+
+    fn value(container: Option<&str>, resource: &str, divisor: Option<&str>) -> Option<String> {
+        resource_value(
+            &pod(),
+            "main",
+            &k8s::ResourceFieldSelector {
+                container_name: container.map(String::from),
+                resource: String::from(resource),
+                divisor: divisor.map(|divisor| Quantity(String::from(divisor))),
+            },
+        )
+    }
+
+    #[test]
+    fn rounds_resources_up_to_the_divisor() {
+        assert_eq!(value(None, "limits.cpu", None).as_deref(), Some("2"));
+        assert_eq!(
+            value(None, "limits.cpu", Some("1m")).as_deref(),
+            Some("1500")
+        );
+        assert_eq!(value(None, "requests.cpu", None).as_deref(), Some("1"));
+        assert_eq!(
+            value(None, "limits.memory", Some("1Mi")).as_deref(),
+            Some("512")
+        );
+        assert_eq!(
+            value(None, "limits.memory", Some("1Gi")).as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            value(Some("migrate"), "limits.memory", Some("1Mi")).as_deref(),
+            Some("1024")
+        );
+    }
+
+    #[test]
+    fn leaves_unknown_resources_and_bad_divisors_unresolved() {
+        assert_eq!(value(None, "requests.memory", None), None);
+        assert_eq!(value(None, "limits.gpu", None), None);
+        assert_eq!(value(None, "cpu", None), None);
+        assert_eq!(value(Some("sidecar"), "limits.cpu", None), None);
+        assert_eq!(value(None, "limits.cpu", Some("0")), None);
+    }
+}
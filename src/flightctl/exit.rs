@@ -0,0 +1,153 @@
+use std::fmt;
+use std::process::ExitStatus;
+
+/// Exit status for failures that don't fall into any category below.
+pub const GENERAL: i32 = 200;
+
+/// Exit status when an external program couldn't be started.
+pub const LAUNCH: i32 = 205;
+
+/// Raised when a command we handed the terminal to (`kubectl exec`, `aws`,
+/// etc.) exits unsuccessfully. Its status is forwarded as our own so callers
+/// see exactly what the remote process returned.
+#[derive(Debug)]
+pub struct ChildExit {
+    pub program: String,
+    pub args: Vec<String>,
+    pub status: ExitStatus,
+}
+
+impl ChildExit {
+    /// The status a shell would report for the child: its exit code, or
+    /// 128 plus the signal number if it was killed by a signal.
+    pub fn code(&self) -> i32 {
+        match self.status.code() {
+            Some(code) => code,
+            None => 128 + signal(&self.status).unwrap_or(0),
+        }
+    }
+}
+
+impl fmt::Display for ChildExit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.status.code(), signal(&self.status)) {
+            (Some(code), _) => write!(
+                f,
+                "{} {:?}: Command exited unsuccessfully (status code {})",
+                self.program, self.args, code
+            ),
+            (None, Some(signal)) => write!(
+                f,
+                "{} {:?}: Command terminated by signal {}",
+                self.program, self.args, signal
+            ),
+            (None, None) => write!(
+                f,
+                "{} {:?}: Command exited unsuccessfully (status code unknown)",
+                self.program, self.args
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ChildExit {}
+
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Raised when an external program couldn't be started at all, usually
+/// because it isn't installed or isn't on the `PATH`.
+#[derive(Debug)]
+pub struct LaunchError {
+    pub program: String,
+}
+
+impl fmt::Display for LaunchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Couldn't run {}; is it installed?", self.program)
+    }
+}
+
+/// The stage of flightctl itself that failed, attached as context so it
+/// can be mapped to an exit status.
+#[derive(Clone, Copy, Debug)]
+pub enum Failure {
+    Config,
+    Selection,
+    Authorization,
+    Context,
+}
+
+impl Failure {
+    pub fn code(&self) -> i32 {
+        match self {
+            Failure::Config => 201,
+            Failure::Selection => 202,
+            Failure::Authorization => 203,
+            Failure::Context => 204,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::Config => write!(f, "Invalid workspace configuration"),
+            Failure::Selection => write!(f, "Couldn't select a release"),
+            Failure::Authorization => write!(f, "Couldn't authorize"),
+            Failure::Context => write!(f, "Couldn't prepare Kubernetes context"),
+        }
+    }
+}
+
+/// Raised by commands that report their result through the exit status,
+/// such as a diff finding differences. Not printed as an error.
+#[derive(Debug)]
+pub struct Outcome {
+    pub code: i32,
+    pub message: String,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Outcome {}
+
+/// Exit status for an error returned from a command.
+pub fn code(err: &anyhow::Error) -> i32 {
+    if let Some(outcome) = err.downcast_ref::<Outcome>() {
+        outcome.code
+    } else if err.downcast_ref::<LaunchError>().is_some() {
+        LAUNCH
+    } else if let Some(failure) = err.downcast_ref::<Failure>() {
+        failure.code()
+    } else if let Some(child) = err.downcast_ref::<ChildExit>() {
+        child.code()
+    } else {
+        GENERAL
+    }
+}
+
+/// Whether the error should be printed. A forwarded child status isn't,
+/// since the child has already reported its own failure, and neither is an
+/// outcome, since the command has already printed its result.
+pub fn should_report(err: &anyhow::Error) -> bool {
+    if err.downcast_ref::<Outcome>().is_some() {
+        return false;
+    }
+
+    err.downcast_ref::<LaunchError>().is_some()
+        || err.downcast_ref::<Failure>().is_some()
+        || err.downcast_ref::<ChildExit>().is_none()
+}
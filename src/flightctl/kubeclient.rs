@@ -1,10 +1,32 @@
+use super::kubeconfig::KubeconfigFile;
 use super::kubectl;
+use super::runner::Runner;
+use super::{Config, Release};
+use k8s_openapi::api::apps::v1 as apps;
 use k8s_openapi::api::core::v1 as k8s;
+use k8s_openapi::NamespaceResourceScope;
+use kube::api::{Api, ListParams, Patch, PatchParams};
+use kube::Resource;
+use serde::de::DeserializeOwned;
+use std::cell::OnceCell;
 use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::IsTerminal;
 
-#[derive(Debug)]
-pub struct KubeClient {
+/// Reads and patches go through an in-process API client for the context,
+/// while interactive commands and other writes are handed to `kubectl`.
+pub struct KubeClient<'r> {
+    runner: &'r dyn Runner,
+    file: KubeconfigFile,
     context: String,
+    api: OnceCell<Result<ApiClient, String>>,
+}
+
+/// Connected on first use, so commands that only hand off to `kubectl` don't
+/// load credentials twice.
+struct ApiClient {
+    runtime: tokio::runtime::Runtime,
+    client: kube::Client,
 }
 
 #[derive(Debug)]
@@ -12,98 +34,250 @@ pub struct Selector {
     labels: HashMap<String, String>,
 }
 
-pub fn new(context: &str) -> KubeClient {
+/// How the local terminal is attached to a command run with `exec`.
+#[derive(Debug)]
+pub struct ExecOptions {
+    pub stdin: bool,
+    pub tty: bool,
+}
+
+impl ExecOptions {
+    /// Passes stdin through unless disabled, and only allocates a TTY when
+    /// both stdin and stdout are terminals so output can be piped cleanly.
+    pub fn detect(no_stdin: bool, no_tty: bool) -> ExecOptions {
+        let stdin = !no_stdin;
+        ExecOptions {
+            stdin,
+            tty: stdin
+                && !no_tty
+                && std::io::stdin().is_terminal()
+                && std::io::stdout().is_terminal(),
+        }
+    }
+}
+
+/// A client for the release's context in the workspace's kubeconfig.
+pub fn new<'r>(runner: &'r dyn Runner, config: &Config, release: &Release) -> KubeClient<'r> {
+    let file = config.kubeconfig_file();
     KubeClient {
-        context: String::from(context),
+        runner,
+        context: file.name(&release.context),
+        file,
+        api: OnceCell::new(),
     }
 }
 
-impl KubeClient {
+impl<'r> KubeClient<'r> {
+    /// Uses the given API client instead of connecting to the context.
+    #[cfg(test)]
+    pub fn with_api(
+        runner: &'r dyn Runner,
+        context: &str,
+        runtime: tokio::runtime::Runtime,
+        client: kube::Client,
+    ) -> KubeClient<'r> {
+        KubeClient {
+            runner,
+            file: KubeconfigFile::default(),
+            context: String::from(context),
+            api: OnceCell::from(Ok(ApiClient { runtime, client })),
+        }
+    }
+
+    /// Finds a running pod matching the selector.
     pub fn get_available_pod(&self, selector: Selector) -> anyhow::Result<k8s::Pod> {
-        let output = kubectl::run_get_output(&[
-            "--context",
-            &self.context,
-            "get",
-            "pod",
-            "--selector",
-            &selector.to_string(),
-            "--field-selector",
-            "status.phase=Running",
-            "--output",
-            "name",
-        ])?;
-        let pod_names = String::from_utf8(output.stdout)?;
-        let pod_name = pod_names
-            .split_whitespace()
-            .nth(0)
-            .ok_or(anyhow::Error::msg("No console pod found"))?;
-        let pod = self.fetch_resource(&pod_name.trim_end())?;
-        Ok(pod)
+        let params = ListParams::default()
+            .labels(&selector.to_string())
+            .fields("status.phase=Running");
+        self.list::<k8s::Pod>(&params)?
+            .into_iter()
+            .next()
+            .ok_or(anyhow::Error::msg("No console pod found"))
+    }
+
+    pub fn get_deployments(&self, selector: Selector) -> anyhow::Result<Vec<apps::Deployment>> {
+        self.list(&ListParams::default().labels(&selector.to_string()))
     }
 
     pub fn get_workloads(&self, selector: Selector) -> anyhow::Result<()> {
-        kubectl::run_print(&[
-            "--context",
-            &self.context,
-            "get",
-            "deploy",
-            "--selector",
-            &selector.to_string(),
-        ])
+        kubectl::run_print(
+            self.runner,
+            &self.args(["get", "deploy", "--selector", &selector.to_string()]),
+        )
     }
 
-    pub fn exec<S>(&self, pod: &k8s::Pod, container: &str, command: &Vec<S>) -> anyhow::Result<()>
+    /// Applies a JSON merge patch to a resource by name. The patch is sent
+    /// in the request body rather than on a `kubectl` command line, since it
+    /// may hold secret values.
+    pub fn patch<K>(&self, name: &str, patch: &serde_json::Value) -> anyhow::Result<()>
+    where
+        K: Resource<Scope = NamespaceResourceScope> + Clone + DeserializeOwned + Debug,
+        K::DynamicType: Default,
+    {
+        let description = format!(
+            "Patch {}/{}",
+            K::kind(&Default::default()).to_lowercase(),
+            name
+        );
+        self.runner.change_in_process(&description, &mut || {
+            let api = self.api()?;
+            let resources: Api<K> = Api::default_namespaced(api.client.clone());
+            api.runtime
+                .block_on(resources.patch(name, &PatchParams::default(), &Patch::Merge(patch)))
+                .map_err(api_error)?;
+            Ok(())
+        })
+    }
+
+    pub fn restart_workloads(&self, selector: Selector) -> anyhow::Result<()> {
+        kubectl::run_change(
+            self.runner,
+            &self.args([
+                "rollout",
+                "restart",
+                "deploy",
+                "--selector",
+                &selector.to_string(),
+            ]),
+        )
+    }
+
+    pub fn exec<S>(
+        &self,
+        pod: &k8s::Pod,
+        container: &str,
+        command: &Vec<S>,
+        options: &ExecOptions,
+    ) -> anyhow::Result<()>
     where
         S: AsRef<str>,
     {
         let pod_name = pod.metadata.name.as_deref();
-        kubectl::run_print(
-            &[
-                vec![
-                    "--context",
-                    &self.context,
-                    "exec",
-                    "--stdin",
-                    "--tty",
-                    &pod_name.unwrap_or_default(),
-                    "--container",
-                    container,
-                    "--",
-                ],
-                command.iter().map(|s| s.as_ref()).collect(),
-            ]
-            .concat(),
-        )
+        let mut args = vec!["exec"];
+        if options.stdin {
+            args.push("--stdin");
+        }
+        if options.tty {
+            args.push("--tty");
+        }
+        args.extend([pod_name.unwrap_or_default(), "--container", container, "--"]);
+        args.extend(command.iter().map(|s| s.as_ref()));
+        kubectl::run_interactive(self.runner, &self.args(args))
     }
 
     pub fn run_command<S>(&self, command: &Vec<S>) -> anyhow::Result<()>
     where
         S: AsRef<str>,
     {
-        kubectl::run_print(
-            &[
-                vec!["--context", &self.context],
-                command.iter().map(|s| s.as_ref()).collect(),
-            ]
-            .concat(),
-        )
+        kubectl::run_interactive(self.runner, &self.args(command.iter().map(|s| s.as_ref())))
+    }
+
+    /// Fetches a resource by name from the context's namespace.
+    pub fn get<K>(&self, name: &str) -> anyhow::Result<K>
+    where
+        K: Resource<Scope = NamespaceResourceScope> + Clone + DeserializeOwned + Debug,
+        K::DynamicType: Default,
+    {
+        let api = self.api()?;
+        let resources: Api<K> = Api::default_namespaced(api.client.clone());
+        self.runner
+            .api_request(&format!("GET {}/{}", resources.resource_url(), name));
+        api.runtime.block_on(resources.get(name)).map_err(api_error)
+    }
+
+    /// Fetches several resources of a kind concurrently, with a result for
+    /// each name in order. Fails as a whole only if there's no connection.
+    pub fn get_many<K>(&self, names: &[&str]) -> anyhow::Result<Vec<Result<K, String>>>
+    where
+        K: Resource<Scope = NamespaceResourceScope> + Clone + DeserializeOwned + Debug,
+        K::DynamicType: Default,
+    {
+        let api = self.api()?;
+        let resources: Api<K> = Api::default_namespaced(api.client.clone());
+        for name in names {
+            self.runner
+                .api_request(&format!("GET {}/{}", resources.resource_url(), name));
+        }
+        let results = api.runtime.block_on(futures::future::join_all(
+            names.iter().map(|name| resources.get(name)),
+        ));
+        Ok(results
+            .into_iter()
+            .map(|result| result.map_err(|err| api_error(err).to_string()))
+            .collect())
     }
 
-    pub fn fetch_resource<T>(&self, resource: &str) -> anyhow::Result<T>
+    /// Lists resources of a kind in the context's namespace.
+    pub fn list<K>(&self, params: &ListParams) -> anyhow::Result<Vec<K>>
     where
-        T: for<'de> serde::Deserialize<'de>,
+        K: Resource<Scope = NamespaceResourceScope> + Clone + DeserializeOwned + Debug,
+        K::DynamicType: Default,
     {
-        let output = kubectl::run_get_output(&[
-            "--context",
-            &self.context,
-            "get",
-            &resource,
-            "--output",
-            "yaml",
-        ])?;
-        let yaml = String::from_utf8(output.stdout)?;
-        let result = serde_yaml::from_str::<T>(&yaml)?;
-        Ok(result)
+        let api = self.api()?;
+        let resources: Api<K> = Api::default_namespaced(api.client.clone());
+        let selectors: Vec<String> = [
+            ("labelSelector", &params.label_selector),
+            ("fieldSelector", &params.field_selector),
+        ]
+        .into_iter()
+        .filter_map(|(name, selector)| Some(format!("{}={}", name, selector.as_ref()?)))
+        .collect();
+        self.runner.api_request(&format!(
+            "GET {}?{}",
+            resources.resource_url(),
+            selectors.join("&")
+        ));
+        let list = api
+            .runtime
+            .block_on(resources.list(params))
+            .map_err(api_error)?;
+        Ok(list.items)
+    }
+
+    /// Arguments for `kubectl` that select this client's context in the
+    /// workspace's kubeconfig, followed by the given arguments.
+    fn args<'a>(&self, args: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+        let mut all = self.file.args();
+        all.extend([String::from("--context"), self.context.clone()]);
+        all.extend(args.into_iter().map(String::from));
+        all
+    }
+
+    fn api(&self) -> anyhow::Result<&ApiClient> {
+        self.api
+            .get_or_init(|| {
+                ApiClient::connect(&self.file, &self.context).map_err(|err| format!("{:#}", err))
+            })
+            .as_ref()
+            .map_err(|err| {
+                anyhow::Error::msg(format!(
+                    "Couldn't connect to Kubernetes context {}: {}",
+                    self.context, err
+                ))
+            })
+    }
+}
+
+impl ApiClient {
+    /// Loads the context from the same kubeconfig `kubectl` uses.
+    fn connect(file: &KubeconfigFile, context: &str) -> anyhow::Result<ApiClient> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let client = runtime.block_on(async {
+            let config = file.client_config(context).await?;
+            anyhow::Ok(kube::Client::try_from(config)?)
+        })?;
+        Ok(ApiClient { runtime, client })
+    }
+}
+
+/// Uses the API server's own message for failed requests, such as
+/// `configmaps "app-config" not found`.
+fn api_error(err: kube::Error) -> anyhow::Error {
+    match err {
+        kube::Error::Api(response) => anyhow::Error::msg(response.message),
+        err => anyhow::Error::new(err),
     }
 }
 
@@ -124,10 +298,162 @@ impl Selector {
     }
 
     pub fn to_string(&self) -> String {
-        self.labels
+        let mut labels = self
+            .labels
             .iter()
             .map(|(key, value)| format!("{}={}", key, value))
-            .collect::<Vec<String>>()
-            .join(",")
+            .collect::<Vec<String>>();
+        labels.sort();
+        labels.join(",")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flightctl::exit;
+    use crate::flightctl::runner::DryRun;
+    use crate::flightctl::testing::{FakeApi, FakeRunner};
+    use serde_json::json;
+
+    const PODS: &str = "/api/v1/namespaces/default/pods";
+
+    fn selector() -> Selector {
+        Selector::new(HashMap::from([
+            (String::from("role"), String::from("console")),
+            (String::from("app"), String::from("web")),
+        ]))
+    }
+
+    fn pod(name: &str) -> serde_json::Value {
+        json!({
+            "apiVersion": "v1",
+            "kind": "Pod",
+            "metadata": { "name": name },
+            "spec": { "containers": [{ "name": "main" }] },
+        })
+    }
+
+    fn pod_list(pods: Vec<serde_json::Value>) -> serde_json::Value {
+        json!({
+            "apiVersion": "v1",
+            "kind": "PodList",
+            "metadata": {},
+            "items": pods,
+        })
+    }
+
+    #[test]
+    fn selects_first_running_pod() {
+        let runner = FakeRunner::new();
+        let api = FakeApi::new().serve(PODS, pod_list(vec![pod("web-1"), pod("web-2")]));
+        let client = api.client(&runner);
+
+        let pod = client.get_available_pod(selector()).unwrap();
+
+        assert_eq!(pod.metadata.name.as_deref(), Some("web-1"));
+        assert_eq!(
+            runner.api_requests(),
+            [format!(
+                "GET {}?labelSelector=app=web,role=console&fieldSelector=status.phase=Running",
+                PODS
+            )]
+        );
+        assert_eq!(
+            api.requests(),
+            [format!(
+                "{}?&fieldSelector=status.phase%3DRunning&labelSelector=app%3Dweb%2Crole%3Dconsole",
+                PODS
+            )]
+        );
+    }
+
+    #[test]
+    fn fails_without_running_pod() {
+        let runner = FakeRunner::new();
+        let api = FakeApi::new().serve(PODS, pod_list(vec![]));
+
+        let err = api
+            .client(&runner)
+            .get_available_pod(selector())
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "No console pod found");
+    }
+
+    #[test]
+    fn reports_api_errors() {
+        let runner = FakeRunner::new();
+        let api = FakeApi::new();
+
+        let err = api
+            .client(&runner)
+            .get::<k8s::ConfigMap>("app-config")
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), r#"configmaps "app-config" not found"#);
+    }
+
+    #[test]
+    fn execs_in_pod_through_kubectl() {
+        let runner = FakeRunner::new()
+            .expect(
+                "kubectl",
+                &[
+                    "--context",
+                    "test",
+                    "exec",
+                    "--stdin",
+                    "web-1",
+                    "--container",
+                    "main",
+                    "--",
+                    "rails",
+                    "console",
+                ],
+            )
+            .fails(3, "");
+        let api = FakeApi::new();
+        let client = api.client(&runner);
+        let pod: k8s::Pod = serde_json::from_value(pod("web-1")).unwrap();
+
+        let err = client
+            .exec(
+                &pod,
+                "main",
+                &vec!["rails", "console"],
+                &ExecOptions {
+                    stdin: true,
+                    tty: false,
+                },
+            )
+            .unwrap_err();
+
+        assert_eq!(exit::code(&err), 3);
+        assert!(api.requests().is_empty());
+    }
+
+    #[test]
+    fn dry_run_prints_exec_without_running_it() {
+        let fake = FakeRunner::new();
+        let runner = DryRun::new(&fake);
+        let api = FakeApi::new();
+        let client = api.client(&runner);
+        let pod: k8s::Pod = serde_json::from_value(pod("web-1")).unwrap();
+
+        client
+            .exec(
+                &pod,
+                "main",
+                &vec!["rails", "console"],
+                &ExecOptions {
+                    stdin: true,
+                    tty: true,
+                },
+            )
+            .unwrap();
+        client.run_command(&vec!["get", "pods"]).unwrap();
+
+        assert!(fake.calls().is_empty());
     }
 }
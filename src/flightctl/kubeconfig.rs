@@ -0,0 +1,108 @@
+use kube::config::{Kubeconfig, KubeconfigError};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Where the workspace keeps its Kubernetes contexts.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum KubeconfigMode {
+    /// The user's kubeconfig, from `KUBECONFIG` or `~/.kube/config`, with
+    /// entries named as in the workspace configuration.
+    #[default]
+    Global,
+
+    /// A kubeconfig of the workspace's own, with entries prefixed by the
+    /// workspace name.
+    Workspace,
+}
+
+/// The kubeconfig a workspace's contexts are read from and written to, and
+/// the names its entries are given there.
+#[derive(Clone, Debug, Default)]
+pub struct KubeconfigFile {
+    path: Option<PathBuf>,
+    prefix: Option<String>,
+}
+
+/// Path of a workspace's own kubeconfig, relative to its root.
+const WORKSPACE_PATH: &str = "tmp/flightctl/kubeconfig";
+
+impl KubeconfigFile {
+    pub fn new(mode: KubeconfigMode, root: &Path) -> KubeconfigFile {
+        match mode {
+            KubeconfigMode::Global => KubeconfigFile::default(),
+            KubeconfigMode::Workspace => KubeconfigFile {
+                path: Some(root.join(WORKSPACE_PATH)),
+                prefix: Some(
+                    root.file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| String::from("flightctl")),
+                ),
+            },
+        }
+    }
+
+    /// The name of a context, cluster or user entry in this file.
+    pub fn name(&self, name: &str) -> String {
+        match &self.prefix {
+            Some(prefix) => format!("{}-{}", prefix, name),
+            None => String::from(name),
+        }
+    }
+
+    /// Arguments that point `kubectl` at this file.
+    pub fn args(&self) -> Vec<String> {
+        match &self.path {
+            Some(path) => vec![
+                String::from("--kubeconfig"),
+                path.to_string_lossy().into_owned(),
+            ],
+            None => vec![],
+        }
+    }
+
+    /// The file entries are written to: the workspace's own file, or else the
+    /// first file in `KUBECONFIG`, or `~/.kube/config`, as for `kubectl`.
+    pub fn write_path(&self) -> anyhow::Result<PathBuf> {
+        if let Some(path) = &self.path {
+            return Ok(path.clone());
+        }
+        if let Some(paths) = std::env::var_os("KUBECONFIG") {
+            if let Some(path) =
+                std::env::split_paths(&paths).find(|path| !path.as_os_str().is_empty())
+            {
+                return Ok(path);
+            }
+        }
+        Ok(super::home_dir()?.join(".kube").join("config"))
+    }
+
+    /// Loads the file, or an empty configuration if it doesn't exist yet.
+    pub fn read(&self) -> anyhow::Result<Kubeconfig> {
+        log::debug!("Loading Kubernetes configuration");
+        let result = match &self.path {
+            Some(path) => Kubeconfig::read_from(path),
+            None => Kubeconfig::read(),
+        };
+        match result {
+            Ok(config) => anyhow::Ok(config),
+            Err(KubeconfigError::ReadConfig { .. }) => anyhow::Ok(Kubeconfig::default()),
+            Err(err) => Err(anyhow::anyhow!(err)),
+        }
+    }
+
+    /// Client configuration for one of the file's contexts.
+    pub async fn client_config(&self, context: &str) -> anyhow::Result<kube::Config> {
+        let options = kube::config::KubeConfigOptions {
+            context: Some(String::from(context)),
+            ..Default::default()
+        };
+        let config = match &self.path {
+            Some(path) => {
+                kube::Config::from_custom_kubeconfig(Kubeconfig::read_from(path)?, &options).await?
+            }
+            None => kube::Config::from_kubeconfig(&options).await?,
+        };
+        Ok(config)
+    }
+}
@@ -1,92 +1,407 @@
-use super::kubectl;
-use kube::config::{NamedAuthInfo, NamedCluster};
-use std::io::Write;
+use super::kubeconfig::KubeconfigFile;
+use super::runner::Runner;
+use anyhow::Context;
+use kube::config::{NamedAuthInfo, NamedCluster, NamedContext};
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 use tempfile::NamedTempFile;
 
-pub fn write_auth(auth: NamedAuthInfo) -> anyhow::Result<()> {
-    let mut args = vec![
-        String::from("config"),
-        String::from("set-credentials"),
-        auth.name,
-    ];
-    if let Some(auth_info) = auth.auth_info {
-        if let Some(exec) = auth_info.exec {
-            if let Some(command) = exec.command {
-                args.push(String::from("--exec-command"));
-                args.push(command);
-            }
+/// Entries to merge into a kubeconfig, replacing any with the same names.
+#[derive(Debug, Default)]
+pub struct Entries {
+    pub auth_infos: Vec<NamedAuthInfo>,
+    pub clusters: Vec<NamedCluster>,
+    pub contexts: Vec<NamedContext>,
+}
 
-            if let Some(api_version) = exec.api_version {
-                args.push(String::from("--exec-api-version"));
-                args.push(api_version)
-            }
+impl Entries {
+    pub fn is_empty(&self) -> bool {
+        self.auth_infos.is_empty() && self.clusters.is_empty() && self.contexts.is_empty()
+    }
 
-            if let Some(exec_args) = exec.args {
-                for arg in exec_args {
-                    args.push(String::from("--exec-arg"));
-                    args.push(arg);
-                }
-            }
+    fn describe(&self) -> String {
+        let names = Names {
+            users: self
+                .auth_infos
+                .iter()
+                .map(|auth| auth.name.clone())
+                .collect(),
+            clusters: self
+                .clusters
+                .iter()
+                .map(|cluster| cluster.name.clone())
+                .collect(),
+            contexts: self
+                .contexts
+                .iter()
+                .map(|context| context.name.clone())
+                .collect(),
+        };
+        names.entries().join(", ")
+    }
+}
+
+/// Names of entries to remove from a kubeconfig.
+#[derive(Debug, Default, PartialEq)]
+pub struct Names {
+    pub users: Vec<String>,
+    pub clusters: Vec<String>,
+    pub contexts: Vec<String>,
+}
+
+impl Names {
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.clusters.is_empty() && self.contexts.is_empty()
+    }
+
+    /// Each name with the kind of entry it is, such as `user staging`.
+    pub fn entries(&self) -> Vec<String> {
+        let users = self.users.iter().map(|name| format!("user {}", name));
+        let clusters = self.clusters.iter().map(|name| format!("cluster {}", name));
+        let contexts = self.contexts.iter().map(|name| format!("context {}", name));
+        users.chain(clusters).chain(contexts).collect()
+    }
+}
+
+/// How many backups of a kubeconfig are kept next to it.
+const BACKUPS: usize = 5;
+
+/// Merges the entries into the file.
+pub fn write(runner: &dyn Runner, file: &KubeconfigFile, entries: Entries) -> anyhow::Result<()> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let path = file.write_path()?;
+    let description = format!("Write {} to {}", entries.describe(), path.display());
+    runner.change_in_process(&description, &mut || {
+        edit(&path, |document| {
+            merge(document, "users", &entries.auth_infos)?;
+            merge(document, "clusters", &entries.clusters)?;
+            merge(document, "contexts", &entries.contexts)
+        })
+    })
+}
 
-            if let Some(env) = &exec.env {
-                for env_map in env {
-                    if let (Some(name), Some(value)) = (env_map.get("name"), env_map.get("value")) {
-                        args.push(String::from("--exec-env"));
-                        args.push(format!("{}={}", name, value));
-                    }
-                }
+/// Removes the named entries from the file, and unsets the current context
+/// if it's one of them.
+pub fn remove(runner: &dyn Runner, file: &KubeconfigFile, names: Names) -> anyhow::Result<()> {
+    if names.is_empty() {
+        return Ok(());
+    }
+    let path = file.write_path()?;
+    let description = format!(
+        "Remove {} from {}",
+        names.entries().join(", "),
+        path.display()
+    );
+    runner.change_in_process(&description, &mut || {
+        edit(&path, |document| {
+            retain(document, "users", &names.users)?;
+            retain(document, "clusters", &names.clusters)?;
+            retain(document, "contexts", &names.contexts)?;
+            let current = document.get(&Value::from("current-context"));
+            if current
+                .and_then(Value::as_str)
+                .is_some_and(|current| names.contexts.iter().any(|name| name == current))
+            {
+                document.remove(&Value::from("current-context"));
             }
-        };
-    };
-    kubectl::run_print(&args)
+            Ok(())
+        })
+    })
 }
 
-pub fn write_context(name: &str, auth: &str, cluster: &str, namespace: &str) -> anyhow::Result<()> {
-    kubectl::run_print(&[
-        "config",
-        "set-context",
-        name,
-        "--cluster",
-        cluster,
-        "--user",
-        auth,
-        "--namespace",
-        namespace,
-    ])
+/// Changes the file in place. Concurrent writers take turns through a lock
+/// file, the previous version is kept as a timestamped backup, and the new
+/// version replaces the old one in a single rename, so the file is never
+/// left half-written.
+fn edit(
+    path: &Path,
+    change: impl FnOnce(&mut Mapping) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    edit_locked(path, change)
+        .with_context(|| format!("Failed to write kubeconfig {}", path.display()))
 }
 
-pub fn write_cluster(definition: NamedCluster) -> anyhow::Result<()> {
-    let cluster = &definition.cluster.ok_or(anyhow::Error::msg(format!(
-        "Missing cluster definition for {}",
-        &definition.name
-    )))?;
-    let mut args = vec![
-        String::from("config"),
-        String::from("set-cluster"),
-        definition.name,
-    ];
+fn edit_locked(
+    path: &Path,
+    change: impl FnOnce(&mut Mapping) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let path = match fs::canonicalize(path) {
+        Ok(target) => target,
+        Err(err) if err.kind() == ErrorKind::NotFound => path.to_path_buf(),
+        Err(err) => return Err(err.into()),
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    fs::create_dir_all(&dir)?;
+
+    let lock = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(sibling(&path, ".flightctl-lock")))?;
+    lock.lock()?;
+
+    // Read under the lock so entries written by another flightctl while this
+    // one waited are kept.
+    let exists = path.exists();
+    let mut document = read_document(&path)?;
+    change(&mut document)?;
 
-    if let Some(server) = &cluster.server {
-        args.push(String::from("--server"));
-        args.push(String::from(server))
+    if exists {
+        backup(&path, &dir)?;
     }
+    let mut temp = NamedTempFile::new_in(&dir)?;
+    serde_yaml::to_writer(&mut temp, &document)?;
+    temp.as_file().sync_all()?;
+    temp.persist(&path)?;
+    Ok(())
+}
 
-    let mut ca_file = NamedTempFile::new()?;
+/// Loads the file as plain YAML rather than as a `Kubeconfig`, so fields
+/// flightctl doesn't know about survive the rewrite.
+fn read_document(path: &Path) -> anyhow::Result<Mapping> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+    let document = if contents.trim().is_empty() {
+        Value::Null
+    } else {
+        serde_yaml::from_str(&contents)?
+    };
+    match document {
+        Value::Mapping(mapping) => Ok(mapping),
+        Value::Null => {
+            let mut mapping = Mapping::new();
+            mapping.insert(Value::from("apiVersion"), Value::from("v1"));
+            mapping.insert(Value::from("kind"), Value::from("Config"));
+            Ok(mapping)
+        }
+        _ => Err(anyhow::Error::msg("The kubeconfig isn't a YAML mapping")),
+    }
+}
 
-    if let Some(ca_data_encoded) = &cluster.certificate_authority_data {
-        let ca_data = base64::decode(&ca_data_encoded)?;
-        ca_file.write(&ca_data)?;
+/// Adds or replaces the entries in one of the document's lists.
+fn merge<T: Serialize>(document: &mut Mapping, key: &str, entries: &[T]) -> anyhow::Result<()> {
+    let list = list(document, key)?;
+    for entry in entries {
+        let entry = serde_yaml::to_value(entry)?;
+        match list
+            .iter_mut()
+            .find(|existing| existing.get("name") == entry.get("name"))
+        {
+            Some(existing) => *existing = entry,
+            None => list.push(entry),
+        }
     }
+    Ok(())
+}
 
-    let ca_path = ca_file.into_temp_path();
-    if cluster.certificate_authority_data.is_some() {
-        let ca_path_name = &ca_path.to_str().unwrap();
-        args.push(String::from("--embed-certs"));
-        args.push(String::from("--certificate-authority"));
-        args.push(ca_path_name.to_string());
+fn retain(document: &mut Mapping, key: &str, removed: &[String]) -> anyhow::Result<()> {
+    list(document, key)?.retain(|entry| {
+        !entry
+            .get("name")
+            .and_then(Value::as_str)
+            .is_some_and(|name| removed.iter().any(|removed| removed == name))
+    });
+    Ok(())
+}
+
+/// One of the document's lists of entries, created if it's missing, since
+/// clients reject a kubeconfig without all three.
+fn list<'d>(document: &'d mut Mapping, key: &str) -> anyhow::Result<&'d mut Vec<Value>> {
+    let list = document
+        .entry(Value::from(key))
+        .or_insert(Value::Sequence(vec![]));
+    if list.is_null() {
+        *list = Value::Sequence(vec![]);
     }
+    list.as_sequence_mut()
+        .ok_or_else(|| anyhow::Error::msg(format!("The kubeconfig's {} aren't a list", key)))
+}
+
+/// Copies the file aside, and removes all but the newest few copies.
+fn backup(path: &Path, dir: &Path) -> anyhow::Result<()> {
+    let prefix = sibling(path, ".flightctl-backup-");
+    let timestamp = chrono::Utc::now().format("%Y%m%dT%H%M%S%.6fZ").to_string();
+    let mut name = prefix.clone();
+    name.push(timestamp);
+    fs::copy(path, dir.join(name))?;
 
-    kubectl::run_print(&args)?;
-    ca_path.close()?;
+    let mut backups: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|backup| {
+            backup
+                .file_name()
+                .and_then(|name| name.to_str())
+                .zip(prefix.to_str())
+                .is_some_and(|(name, prefix)| name.starts_with(prefix))
+        })
+        .collect();
+    backups.sort();
+    let excess = backups.len().saturating_sub(BACKUPS);
+    for old in &backups[..excess] {
+        fs::remove_file(old)?;
+    }
     Ok(())
 }
+
+/// The file's name with a suffix, for files kept next to it.
+fn sibling(path: &Path, suffix: &str) -> OsString {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flightctl::kubeconfig::KubeconfigMode;
+    use crate::flightctl::runner::DryRun;
+    use crate::flightctl::testing::FakeRunner;
+
+    const EXISTING: &str = r#"
+apiVersion: v1
+kind: Config
+current-context: other
+preferences: {}
+clusters:
+- name: other
+  cluster:
+    server: https://other.example.com
+    disable-compression: true
+- name: acme-cluster
+  cluster:
+    server: https://old.example.com
+users: []
+contexts: []
+"#;
+
+    fn file(dir: &Path) -> KubeconfigFile {
+        KubeconfigFile::new(KubeconfigMode::Workspace, &dir.join("acme"))
+    }
+
+    fn cluster(server: &str) -> Entries {
+        Entries {
+            clusters: vec![NamedCluster {
+                name: String::from("acme-cluster"),
+                cluster: Some(kube::config::Cluster {
+                    server: Some(String::from(server)),
+                    ..Default::default()
+                }),
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn backups(path: &Path) -> usize {
+        fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with("kubeconfig.flightctl-backup-")
+            })
+            .count()
+    }
+
+    #[test]
+    fn replaces_entries_and_keeps_the_rest() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = file(dir.path());
+        let path = file.write_path().unwrap();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, EXISTING).unwrap();
+
+        write(
+            &FakeRunner::new(),
+            &file,
+            cluster("https://new.example.com"),
+        )
+        .unwrap();
+
+        let written: Value = serde_yaml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written["current-context"], Value::from("other"));
+        assert_eq!(
+            written["clusters"][0]["cluster"]["disable-compression"],
+            Value::from(true)
+        );
+        assert_eq!(
+            written["clusters"][1]["cluster"]["server"],
+            Value::from("https://new.example.com")
+        );
+        assert_eq!(written["clusters"].as_sequence().unwrap().len(), 2);
+        assert_eq!(backups(&path), 1);
+    }
+
+    #[test]
+    fn creates_the_file_and_prunes_old_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = file(dir.path());
+        let path = file.write_path().unwrap();
+
+        for attempt in 0..BACKUPS + 3 {
+            let server = format!("https://{}.example.com", attempt);
+            write(&FakeRunner::new(), &file, cluster(&server)).unwrap();
+        }
+
+        let written = kube::config::Kubeconfig::read_from(&path).unwrap();
+        assert_eq!(written.clusters.len(), 1);
+        assert_eq!(backups(&path), BACKUPS);
+    }
+
+    #[test]
+    fn removes_entries_and_the_current_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = file(dir.path());
+        let path = file.write_path().unwrap();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(
+            &path,
+            EXISTING.replace("current-context: other", "current-context: acme-staging"),
+        )
+        .unwrap();
+        let names = Names {
+            clusters: vec![String::from("acme-cluster")],
+            contexts: vec![String::from("acme-staging")],
+            ..Default::default()
+        };
+
+        remove(&FakeRunner::new(), &file, names).unwrap();
+
+        let written = kube::config::Kubeconfig::read_from(&path).unwrap();
+        assert_eq!(written.clusters.len(), 1);
+        assert_eq!(written.clusters[0].name, "other");
+        assert_eq!(written.current_context, None);
+        assert_eq!(backups(&path), 1);
+    }
+
+    #[test]
+    fn dry_run_leaves_the_file_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = file(dir.path());
+        let fake = FakeRunner::new();
+
+        write(
+            &DryRun::new(&fake),
+            &file,
+            cluster("https://new.example.com"),
+        )
+        .unwrap();
+
+        assert!(!file.write_path().unwrap().exists());
+    }
+}
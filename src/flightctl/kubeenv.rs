@@ -1,72 +1,275 @@
+use super::downward;
 use super::kubeclient::KubeClient;
 use k8s_openapi::api::core::v1 as k8s;
-use std::collections::HashMap;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
 use std::rc::Rc;
 
 pub struct ResolvedVar {
     pub name: Rc<String>,
     pub value: ResolvedValue,
+
+    /// Set when a later definition of the same name takes precedence, so
+    /// this one never reaches the container.
+    pub shadowed: bool,
+}
+
+pub struct ResolvedEnv {
+    pub vars: Vec<ResolvedVar>,
+    pub errors: Vec<ResolveError>,
+}
+
+/// A non-optional source that the container can't start without.
+#[derive(Debug)]
+pub enum ResolveError {
+    MissingSource {
+        kind: &'static str,
+        name: Rc<String>,
+        reason: String,
+    },
+    MissingKey {
+        kind: &'static str,
+        name: Rc<String>,
+        key: Rc<String>,
+    },
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResolveError::MissingSource { kind, name, reason } => {
+                write!(f, "Couldn't fetch {}/{}: {}", kind, name, reason.trim())
+            }
+            ResolveError::MissingKey { kind, name, key } => {
+                write!(f, "Key {} not found in {}/{}", key, kind, name)
+            }
+        }
+    }
 }
 
 pub enum ResolvedValue {
     Pod {
         value: Option<String>,
+        expanded: Option<Expansion>,
     },
     ConfigMapKeyRef {
         config_map: Rc<String>,
         key: Rc<String>,
-        value: Option<Rc<String>>,
+        value: Result<Rc<String>, Missing>,
     },
     SecretKeyRef {
         secret: Rc<String>,
         key: Rc<String>,
+        value: Result<Rc<String>, Missing>,
     },
     FieldRef {
         path: String,
+        value: Option<String>,
+    },
+    ResourceFieldRef {
+        container: Option<String>,
+        resource: String,
+        divisor: Option<String>,
+        value: Option<String>,
     },
 }
 
+impl ResolvedValue {
+    /// The value as seen by other variables referencing it with `$(NAME)`,
+    /// or `None` if it couldn't be resolved.
+    pub fn segments(&self) -> Option<Vec<Segment>> {
+        match self {
+            ResolvedValue::Pod {
+                expanded: Some(expansion),
+                ..
+            } => Some(expansion.segments.clone()),
+            ResolvedValue::Pod { value, .. } => {
+                Some(vec![Segment::Text(value.clone().unwrap_or_default())])
+            }
+            ResolvedValue::ConfigMapKeyRef { value, .. } => value
+                .as_ref()
+                .ok()
+                .map(|value| vec![Segment::Text(value.to_string())]),
+            ResolvedValue::SecretKeyRef { value, .. } => value
+                .as_ref()
+                .ok()
+                .map(|value| vec![Segment::Secret(value.to_string())]),
+            ResolvedValue::FieldRef { value, .. }
+            | ResolvedValue::ResourceFieldRef { value, .. } => {
+                value.clone().map(|value| vec![Segment::Text(value)])
+            }
+        }
+    }
+}
+
+/// The result of expanding `$(NAME)` references in a pod value, keeping
+/// track of which parts came from secrets so they can be masked.
+#[derive(Clone, Debug)]
+pub struct Expansion {
+    pub segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Segment {
+    Text(String),
+    Secret(String),
+}
+
+impl Expansion {
+    pub fn value(&self) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) | Segment::Secret(text) => text.as_str(),
+            })
+            .collect()
+    }
+
+    pub fn masked(&self) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text.as_str(),
+                Segment::Secret(_) => "********",
+            })
+            .collect()
+    }
+
+    pub fn has_secret(&self) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| matches!(segment, Segment::Secret(_)))
+    }
+}
+
+/// Why a key reference couldn't be resolved.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Missing {
+    Source,
+    Key,
+}
+
 pub struct Resolver<'c> {
     cache: Cache<'c>,
+    errors: Vec<ResolveError>,
 }
 
 impl<'c> Resolver<'c> {
-    pub fn new(client: &'c KubeClient) -> Resolver<'c> {
+    pub fn new(client: &'c KubeClient<'c>) -> Resolver<'c> {
         Resolver {
             cache: Cache::new(client),
+            errors: Vec::new(),
         }
     }
 
-    pub fn resolve(&mut self, container: k8s::Container) -> Vec<ResolvedVar> {
+    /// Resolves a container's environment with the kubelet's precedence:
+    /// `envFrom` sources in order, then `env` entries in order, with later
+    /// definitions of a name replacing earlier ones.
+    pub fn resolve(&mut self, pod: &k8s::Pod, container: k8s::Container) -> ResolvedEnv {
+        self.prefetch([&container]);
         let mut env: Vec<ResolvedVar> = Vec::new();
-        let container_envs = container.env.unwrap_or(vec![]);
-        env.append(&mut self.container_env_values(container_envs));
         let container_env_sources = container.env_from.unwrap_or(vec![]);
         env.append(&mut self.container_env_from_values(container_env_sources));
+        let container_envs = container.env.unwrap_or(vec![]);
+        let mut explicit_env = self.container_env_values(pod, &container.name, container_envs);
+        expand_references(&env, &mut explicit_env);
+        env.append(&mut explicit_env);
+        mark_shadowed(&mut env);
         env.sort_by(|a, b| a.name.cmp(&b.name));
-        env
+        ResolvedEnv {
+            vars: env,
+            errors: std::mem::take(&mut self.errors),
+        }
     }
 
-    fn container_env_values(&mut self, container_envs: Vec<k8s::EnvVar>) -> Vec<ResolvedVar> {
+    /// Fetches every ConfigMap and Secret the containers refer to up front,
+    /// rather than one at a time while resolving.
+    pub fn prefetch<'a>(&mut self, containers: impl IntoIterator<Item = &'a k8s::Container>) {
+        let mut config_maps = BTreeSet::new();
+        let mut secrets = BTreeSet::new();
+
+        for container in containers {
+            for env_source in container.env_from.iter().flatten() {
+                if let Some(name) = env_source
+                    .config_map_ref
+                    .as_ref()
+                    .and_then(|r| r.name.as_ref())
+                {
+                    config_maps.insert(name.as_str());
+                }
+                if let Some(name) = env_source.secret_ref.as_ref().and_then(|r| r.name.as_ref()) {
+                    secrets.insert(name.as_str());
+                }
+            }
+            for value_from in container
+                .env
+                .iter()
+                .flatten()
+                .filter_map(|var| var.value_from.as_ref())
+            {
+                if let Some(name) = value_from
+                    .config_map_key_ref
+                    .as_ref()
+                    .and_then(|r| r.name.as_ref())
+                {
+                    config_maps.insert(name.as_str());
+                }
+                if let Some(name) = value_from
+                    .secret_key_ref
+                    .as_ref()
+                    .and_then(|r| r.name.as_ref())
+                {
+                    secrets.insert(name.as_str());
+                }
+            }
+        }
+
+        self.cache.prefetch(&config_maps, &secrets);
+    }
+
+    fn container_env_values(
+        &mut self,
+        pod: &k8s::Pod,
+        container: &str,
+        container_envs: Vec<k8s::EnvVar>,
+    ) -> Vec<ResolvedVar> {
         let mut vars = Vec::new();
         for env_var in container_envs {
             let name_ref = Rc::new(env_var.name);
             vars.push(match (env_var.value, env_var.value_from) {
-                (None, Some(value_from)) => self.env_from_value(&name_ref, value_from),
+                (None, Some(value_from)) => {
+                    self.env_from_value(pod, container, &name_ref, value_from)
+                }
                 (Some(value), _) => ResolvedVar {
                     name: name_ref,
-                    value: ResolvedValue::Pod { value: Some(value) },
+                    value: ResolvedValue::Pod {
+                        value: Some(value),
+                        expanded: None,
+                    },
+                    shadowed: false,
                 },
                 (None, None) => ResolvedVar {
                     name: name_ref,
-                    value: ResolvedValue::Pod { value: None },
+                    value: ResolvedValue::Pod {
+                        value: None,
+                        expanded: None,
+                    },
+                    shadowed: false,
                 },
             })
         }
         vars
     }
 
-    fn env_from_value(&mut self, name: &Rc<String>, value_from: k8s::EnvVarSource) -> ResolvedVar {
+    fn env_from_value(
+        &mut self,
+        pod: &k8s::Pod,
+        container: &str,
+        name: &Rc<String>,
+        value_from: k8s::EnvVarSource,
+    ) -> ResolvedVar {
         let value = match (
             value_from.config_map_key_ref,
             value_from.field_ref,
@@ -75,57 +278,229 @@ impl<'c> Resolver<'c> {
         ) {
             (Some(config_map_key_ref), _, _, _) => match config_map_key_ref.name {
                 Some(config_map_name) => {
-                    let config_map_ref = Rc::new(config_map_name);
-                    self.cache
-                        .reference_config_map_key(&config_map_ref, &config_map_key_ref.key)
+                    let value = self
+                        .cache
+                        .reference_config_map_key(&config_map_name, &config_map_key_ref.key);
+                    if !config_map_key_ref.optional.unwrap_or(false) {
+                        self.check_reference(&value);
+                    }
+                    value
                 }
                 None => ResolvedValue::ConfigMapKeyRef {
                     config_map: Rc::new(String::from("null")),
                     key: Rc::new(config_map_key_ref.key),
-                    value: None,
+                    value: Err(Missing::Source),
                 },
             },
             (_, Some(field_ref), _, _) => ResolvedValue::FieldRef {
+                value: downward::field_value(pod, &field_ref.field_path),
                 path: field_ref.field_path,
             },
-            (_, _, _, _) => ResolvedValue::Pod { value: None },
+            (_, _, Some(resource_field_ref), _) => ResolvedValue::ResourceFieldRef {
+                value: downward::resource_value(pod, container, &resource_field_ref),
+                container: resource_field_ref.container_name,
+                resource: resource_field_ref.resource,
+                divisor: resource_field_ref.divisor.map(|divisor| divisor.0),
+            },
+            (_, _, _, Some(secret_key_ref)) => match secret_key_ref.name {
+                Some(secret_name) => {
+                    let value = self
+                        .cache
+                        .reference_secret_key(&secret_name, &secret_key_ref.key);
+                    if !secret_key_ref.optional.unwrap_or(false) {
+                        self.check_reference(&value);
+                    }
+                    value
+                }
+                None => ResolvedValue::SecretKeyRef {
+                    secret: Rc::new(String::from("null")),
+                    key: Rc::new(secret_key_ref.key),
+                    value: Err(Missing::Source),
+                },
+            },
+            (_, _, _, _) => ResolvedValue::Pod {
+                value: None,
+                expanded: None,
+            },
         };
         ResolvedVar {
             name: Rc::clone(name),
             value: value,
+            shadowed: false,
         }
     }
 
+    fn check_reference(&mut self, value: &ResolvedValue) {
+        let (kind, name, key, missing) = match value {
+            ResolvedValue::ConfigMapKeyRef {
+                config_map,
+                key,
+                value: Err(missing),
+            } => ("configmap", config_map, key, missing),
+            ResolvedValue::SecretKeyRef {
+                secret,
+                key,
+                value: Err(missing),
+            } => ("secret", secret, key, missing),
+            _ => return,
+        };
+
+        self.errors.push(match missing {
+            Missing::Source => ResolveError::MissingSource {
+                kind,
+                name: Rc::clone(name),
+                reason: self.cache.failure(kind, name),
+            },
+            Missing::Key => ResolveError::MissingKey {
+                kind,
+                name: Rc::clone(name),
+                key: Rc::clone(key),
+            },
+        });
+    }
+
     fn container_env_from_values(
         &mut self,
         env_sources: Vec<k8s::EnvFromSource>,
     ) -> Vec<ResolvedVar> {
         let mut vars = Vec::new();
         for env_source in env_sources {
-            match (env_source.config_map_ref, env_source.secret_ref) {
-                (
-                    Some(k8s::ConfigMapEnvSource {
-                        name: Some(name), ..
-                    }),
-                    _,
-                ) => {
-                    vars.append(&mut self.cache.import_config_map(&name));
-                }
-                (
-                    None,
-                    Some(k8s::SecretEnvSource {
-                        name: Some(name), ..
-                    }),
-                ) => {
-                    vars.append(&mut self.cache.import_secret(&name));
+            let prefix = env_source.prefix.unwrap_or_default();
+            let (kind, name, optional, imported) =
+                match (env_source.config_map_ref, env_source.secret_ref) {
+                    (
+                        Some(k8s::ConfigMapEnvSource {
+                            name: Some(name),
+                            optional,
+                        }),
+                        _,
+                    ) => {
+                        let imported = self.cache.import_config_map(&name, &prefix);
+                        ("configmap", name, optional, imported)
+                    }
+                    (
+                        None,
+                        Some(k8s::SecretEnvSource {
+                            name: Some(name),
+                            optional,
+                        }),
+                    ) => {
+                        let imported = self.cache.import_secret(&name, &prefix);
+                        ("secret", name, optional, imported)
+                    }
+                    _ => continue,
+                };
+
+            match imported {
+                Some(mut imported) => vars.append(&mut imported),
+                None if !optional.unwrap_or(false) => {
+                    let reason = self.cache.failure(kind, &name);
+                    self.errors.push(ResolveError::MissingSource {
+                        kind,
+                        name: Rc::new(name),
+                        reason,
+                    })
                 }
-                _ => (),
+                None => (),
             }
         }
         vars
     }
 }
 
+fn mark_shadowed(vars: &mut [ResolvedVar]) {
+    let mut seen = std::collections::HashSet::new();
+    for var in vars.iter_mut().rev() {
+        var.shadowed = !seen.insert(Rc::clone(&var.name));
+    }
+}
+
+/// Applies Kubernetes' dependent variable expansion to literal `env`
+/// values. Each value can refer to anything imported with `envFrom` and to
+/// any `env` entry defined before it; other references are left intact.
+fn expand_references(sources: &[ResolvedVar], vars: &mut [ResolvedVar]) {
+    let mut mapping: HashMap<Rc<String>, Vec<Segment>> = sources
+        .iter()
+        .filter_map(|var| Some((Rc::clone(&var.name), var.value.segments()?)))
+        .collect();
+
+    for var in vars.iter_mut() {
+        if let ResolvedValue::Pod {
+            value: Some(template),
+            expanded,
+        } = &mut var.value
+        {
+            if template.contains('$') {
+                *expanded = Some(Expansion {
+                    segments: expand(template, &mapping),
+                });
+            }
+        }
+
+        if let Some(segments) = var.value.segments() {
+            mapping.insert(Rc::clone(&var.name), segments);
+        }
+    }
+}
+
+/// Port of the expansion used by the kubelet: `$(NAME)` is replaced when
+/// NAME is defined, `$$` escapes a literal `$`, and anything else is kept.
+fn expand(template: &str, mapping: &HashMap<Rc<String>, Vec<Segment>>) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut rest = template;
+
+    while let Some(index) = rest.find('$') {
+        text.push_str(&rest[..index]);
+        let after = &rest[index + 1..];
+
+        if let Some(escaped) = after.strip_prefix('$') {
+            text.push('$');
+            rest = escaped;
+        } else if let Some(reference) = after.strip_prefix('(') {
+            match reference.find(')') {
+                Some(close) => {
+                    let name = &reference[..close];
+                    match mapping.get(&String::from(name)) {
+                        Some(value) => {
+                            for segment in value {
+                                match segment {
+                                    Segment::Text(value) => text.push_str(value),
+                                    Segment::Secret(_) => {
+                                        if !text.is_empty() {
+                                            segments.push(Segment::Text(std::mem::take(&mut text)));
+                                        }
+                                        segments.push(segment.clone());
+                                    }
+                                }
+                            }
+                        }
+                        None => {
+                            text.push_str("$(");
+                            text.push_str(name);
+                            text.push(')');
+                        }
+                    }
+                    rest = &reference[close + 1..];
+                }
+                None => {
+                    text.push_str("$(");
+                    rest = reference;
+                }
+            }
+        } else {
+            text.push('$');
+            rest = after;
+        }
+    }
+
+    text.push_str(rest);
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    segments
+}
+
 struct SharedMap {
     data: HashMap<Rc<String>, Rc<String>>,
 }
@@ -167,14 +542,16 @@ impl SharedMap {
     }
 }
 
+/// Fetched sources by name. Failed fetches are kept along with the reason
+/// so each source is only requested once.
 struct Cache<'c> {
-    client: &'c KubeClient,
-    config_maps: HashMap<Rc<String>, SharedMap>,
-    secrets: HashMap<Rc<String>, SharedMap>,
+    client: &'c KubeClient<'c>,
+    config_maps: HashMap<Rc<String>, Result<SharedMap, String>>,
+    secrets: HashMap<Rc<String>, Result<SharedMap, String>>,
 }
 
 impl<'c> Cache<'c> {
-    fn new(client: &'c KubeClient) -> Cache<'c> {
+    fn new(client: &'c KubeClient<'c>) -> Cache<'c> {
         Cache {
             client: client,
             config_maps: HashMap::new(),
@@ -182,6 +559,49 @@ impl<'c> Cache<'c> {
         }
     }
 
+    /// Fetches every source that isn't cached yet concurrently. If there's
+    /// no connection at all, sources are left to be fetched one at a time
+    /// so each gets its own error.
+    fn prefetch(&mut self, config_maps: &BTreeSet<&str>, secrets: &BTreeSet<&str>) {
+        let config_maps: Vec<&str> = config_maps
+            .iter()
+            .copied()
+            .filter(|name| !self.config_maps.contains_key(&String::from(*name)))
+            .collect();
+        let secrets: Vec<&str> = secrets
+            .iter()
+            .copied()
+            .filter(|name| !self.secrets.contains_key(&String::from(*name)))
+            .collect();
+        if config_maps.len() + secrets.len() < 2 {
+            return;
+        }
+
+        match self.client.get_many::<k8s::ConfigMap>(&config_maps) {
+            Ok(results) => {
+                for (name, result) in config_maps.iter().zip(results) {
+                    self.config_maps.insert(
+                        Rc::new(String::from(*name)),
+                        result.map(SharedMap::from_config_map),
+                    );
+                }
+            }
+            Err(err) => log::debug!("Couldn't prefetch configmaps: {:?}", err),
+        }
+
+        match self.client.get_many::<k8s::Secret>(&secrets) {
+            Ok(results) => {
+                for (name, result) in secrets.iter().zip(results) {
+                    self.secrets.insert(
+                        Rc::new(String::from(*name)),
+                        result.map(SharedMap::from_secret),
+                    );
+                }
+            }
+            Err(err) => log::debug!("Couldn't prefetch secrets: {:?}", err),
+        }
+    }
+
     fn reference_config_map_key(&mut self, name: &str, key: &str) -> ResolvedValue {
         let name_ref = Rc::new(String::from(name));
         let key_ref = Rc::new(String::from(key));
@@ -194,78 +614,314 @@ impl<'c> Cache<'c> {
         }
     }
 
-    fn lookup_config_map_key(&self, name: &Rc<String>, key: &Rc<String>) -> Option<Rc<String>> {
-        let config_map = self.config_maps.get(name)?;
-        let value = config_map.data.get(key)?;
-        Some(Rc::clone(value))
+    fn lookup_config_map_key(
+        &self,
+        name: &Rc<String>,
+        key: &Rc<String>,
+    ) -> Result<Rc<String>, Missing> {
+        lookup_key(self.config_maps.get(name), key)
     }
 
-    fn import_config_map(&mut self, name: &str) -> Vec<ResolvedVar> {
+    fn import_config_map(&mut self, name: &str, prefix: &str) -> Option<Vec<ResolvedVar>> {
         let name_ref = Rc::new(String::from(name));
         self.fetch_config_map(&name_ref);
         match self.config_maps.get(&name_ref) {
-            Some(config_map) => config_map
-                .data
-                .iter()
-                .map(|(key, value)| ResolvedVar {
-                    name: Rc::clone(key),
-                    value: ResolvedValue::ConfigMapKeyRef {
-                        config_map: Rc::clone(&name_ref),
-                        key: Rc::clone(key),
-                        value: Some(Rc::clone(value)),
-                    },
-                })
-                .collect(),
-            None => Vec::new(),
+            Some(Ok(config_map)) => Some(
+                config_map
+                    .data
+                    .iter()
+                    .map(|(key, value)| ResolvedVar {
+                        name: prefixed(prefix, key),
+                        value: ResolvedValue::ConfigMapKeyRef {
+                            config_map: Rc::clone(&name_ref),
+                            key: Rc::clone(key),
+                            value: Ok(Rc::clone(value)),
+                        },
+                        shadowed: false,
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    fn failure(&self, kind: &str, name: &str) -> String {
+        let fetched = match kind {
+            "configmap" => self.config_maps.get(&String::from(name)),
+            _ => self.secrets.get(&String::from(name)),
+        };
+        match fetched {
+            Some(Err(reason)) => reason.clone(),
+            _ => String::from("not found"),
         }
     }
 
     fn fetch_config_map(&mut self, name: &Rc<String>) {
         if !self.config_maps.contains_key(name) {
-            match self
-                .client
-                .fetch_resource::<k8s::ConfigMap>(&format!("configmap/{}", &name))
-            {
+            match self.client.get::<k8s::ConfigMap>(name) {
                 Ok(config_map) => {
                     self.config_maps
-                        .insert(Rc::clone(name), SharedMap::from_config_map(config_map));
+                        .insert(Rc::clone(name), Ok(SharedMap::from_config_map(config_map)));
+                }
+                Err(err) => {
+                    log::debug!("Couldn't fetch configmap/{}: {:?}", name, err);
+                    self.config_maps
+                        .insert(Rc::clone(name), Err(err.to_string()));
                 }
-                Err(_) => (),
             }
         }
     }
 
-    fn import_secret(&mut self, name: &str) -> Vec<ResolvedVar> {
+    fn import_secret(&mut self, name: &str, prefix: &str) -> Option<Vec<ResolvedVar>> {
         let name_ref = Rc::new(String::from(name));
         self.fetch_secret(&name_ref);
         match self.secrets.get(&name_ref) {
-            Some(secret) => secret
-                .data
-                .iter()
-                .map(|(key, _)| ResolvedVar {
-                    name: Rc::clone(key),
-                    value: ResolvedValue::SecretKeyRef {
-                        secret: Rc::clone(&name_ref),
-                        key: Rc::clone(key),
-                    },
-                })
-                .collect(),
-            None => Vec::new(),
+            Some(Ok(secret)) => Some(
+                secret
+                    .data
+                    .iter()
+                    .map(|(key, value)| ResolvedVar {
+                        name: prefixed(prefix, key),
+                        value: ResolvedValue::SecretKeyRef {
+                            secret: Rc::clone(&name_ref),
+                            key: Rc::clone(key),
+                            value: Ok(Rc::clone(value)),
+                        },
+                        shadowed: false,
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    fn reference_secret_key(&mut self, name: &str, key: &str) -> ResolvedValue {
+        let name_ref = Rc::new(String::from(name));
+        let key_ref = Rc::new(String::from(key));
+        self.fetch_secret(&name_ref);
+        let value = lookup_key(self.secrets.get(&name_ref), &key_ref);
+        ResolvedValue::SecretKeyRef {
+            secret: name_ref,
+            key: key_ref,
+            value,
         }
     }
 
     fn fetch_secret(&mut self, name: &Rc<String>) {
         if !self.secrets.contains_key(name) {
-            match self
-                .client
-                .fetch_resource::<k8s::Secret>(&format!("secret/{}", &name))
-            {
+            match self.client.get::<k8s::Secret>(name) {
                 Ok(secret) => {
                     self.secrets
-                        .insert(Rc::clone(name), SharedMap::from_secret(secret));
+                        .insert(Rc::clone(name), Ok(SharedMap::from_secret(secret)));
+                }
+                Err(err) => {
+                    log::debug!("Couldn't fetch secret/{}: {:?}", name, err);
+                    self.secrets.insert(Rc::clone(name), Err(err.to_string()));
                 }
-                Err(_) => (),
             }
         }
     }
 }
+
+fn lookup_key(
+    source: Option<&Result<SharedMap, String>>,
+    key: &Rc<String>,
+) -> Result<Rc<String>, Missing> {
+    let source = source
+        .and_then(|source| source.as_ref().ok())
+        .ok_or(Missing::Source)?;
+    let value = source.data.get(key).ok_or(Missing::Key)?;
+    Ok(Rc::clone(value))
+}
+
+fn prefixed(prefix: &str, key: &Rc<String>) -> Rc<String> {
+    if prefix.is_empty() {
+        Rc::clone(key)
+    } else {
+        Rc::new(format!("{}{}", prefix, key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flightctl::testing::{FakeApi, FakeRunner};
+    use serde_json::json;
+
+    const CONFIG_MAPS: &str = "/api/v1/namespaces/default/configmaps";
+    const SECRETS: &str = "/api/v1/namespaces/default/secrets";
+
+    fn container(value: serde_json::Value) -> k8s::Container {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn fetches_each_source_once() {
+        let runner = FakeRunner::new();
+        let api = FakeApi::new()
+            .serve(
+                &format!("{}/app-config", CONFIG_MAPS),
+                json!({
+                    "apiVersion": "v1",
+                    "kind": "ConfigMap",
+                    "metadata": { "name": "app-config" },
+                    "data": { "HOST": "example.com" },
+                }),
+            )
+            .serve(
+                &format!("{}/app-secrets", SECRETS),
+                json!({
+                    "apiVersion": "v1",
+                    "kind": "Secret",
+                    "metadata": { "name": "app-secrets" },
+                    "data": { "password": "aHVudGVyMg==" },
+                }),
+            );
+        let client = api.client(&runner);
+        let key_ref =
+            |kind: &str, name: &str, key: &str| json!({ kind: { "name": name, "key": key } });
+        let containers = [
+            container(json!({
+                "name": "main",
+                "envFrom": [
+                    { "configMapRef": { "name": "app-config" } },
+                    { "secretRef": { "name": "app-secrets" } },
+                ],
+                "env": [
+                    { "name": "A", "valueFrom": key_ref("configMapKeyRef", "app-config", "HOST") },
+                    { "name": "B", "valueFrom": key_ref("secretKeyRef", "app-secrets", "password") },
+                    { "name": "C", "valueFrom": key_ref("configMapKeyRef", "other-config", "x") },
+                ],
+            })),
+            container(json!({
+                "name": "worker",
+                "envFrom": [{ "configMapRef": { "name": "app-config" } }],
+                "env": [
+                    { "name": "D", "valueFrom": key_ref("secretKeyRef", "app-secrets", "token") },
+                    { "name": "E", "valueFrom": key_ref("secretKeyRef", "other-secrets", "y") },
+                    { "name": "F", "valueFrom": key_ref("configMapKeyRef", "other-config", "z") },
+                ],
+            })),
+        ];
+        let pod = k8s::Pod::default();
+
+        let mut resolver = Resolver::new(&client);
+        resolver.prefetch(&containers);
+        for container in containers {
+            resolver.resolve(&pod, container);
+        }
+
+        let mut requests = api.requests();
+        requests.sort();
+        assert_eq!(
+            requests,
+            [
+                format!("{}/app-config", CONFIG_MAPS),
+                format!("{}/other-config", CONFIG_MAPS),
+                format!("{}/app-secrets", SECRETS),
+                format!("{}/other-secrets", SECRETS),
+            ]
+        );
+    }
+
+    fn mapping(vars: &[(&str, Segment)]) -> HashMap<Rc<String>, Vec<Segment>> {
+        vars.iter()
+            .map(|(name, segment)| (Rc::new(String::from(*name)), vec![segment.clone()]))
+            .collect()
+    }
+
+    fn expanded(template: &str, mapping: &HashMap<Rc<String>, Vec<Segment>>) -> Expansion {
+        Expansion {
+            segments: expand(template, mapping),
+        }
+    }
+
+    #[test]
+    fn expands_references_like_the_kubelet() {
+        let mapping = mapping(&[("HOST", Segment::Text(String::from("db")))]);
+
+        assert_eq!(expanded("$(HOST):5432", &mapping).value(), "db:5432");
+        assert_eq!(
+            expanded("$$(HOST) costs $$5", &mapping).value(),
+            "$(HOST) costs $5"
+        );
+        assert_eq!(expanded("$(PORT)/$(HOST)", &mapping).value(), "$(PORT)/db");
+        assert_eq!(expanded("$(HOST", &mapping).value(), "$(HOST");
+        assert_eq!(expanded("trailing $", &mapping).value(), "trailing $");
+    }
+
+    #[test]
+    fn masks_only_secret_segments() {
+        let mapping = mapping(&[
+            ("USER", Segment::Text(String::from("app"))),
+            ("PASSWORD", Segment::Secret(String::from("hunter2"))),
+        ]);
+
+        let expansion = expanded("postgres://$(USER):$(PASSWORD)@db/app", &mapping);
+
+        assert!(expansion.has_secret());
+        assert_eq!(expansion.value(), "postgres://app:hunter2@db/app");
+        assert_eq!(expansion.masked(), "postgres://app:********@db/app");
+        assert!(!expanded("$(USER)", &mapping).has_secret());
+    }
+
+    #[test]
+    fn marks_earlier_definitions_as_shadowed() {
+        let var = |name: &str| ResolvedVar {
+            name: Rc::new(String::from(name)),
+            value: ResolvedValue::Pod {
+                value: None,
+                expanded: None,
+            },
+            shadowed: false,
+        };
+        let mut vars = [var("A"), var("B"), var("A"), var("A")];
+
+        mark_shadowed(&mut vars);
+
+        let shadowed: Vec<bool> = vars.iter().map(|var| var.shadowed).collect();
+        assert_eq!(shadowed, [true, false, true, false]);
+    }
+
+    #[test]
+    fn prefixes_imports_and_skips_optional_sources() {
+        let runner = FakeRunner::new();
+        let api = FakeApi::new().serve(
+            &format!("{}/app-config", CONFIG_MAPS),
+            json!({
+                "apiVersion": "v1",
+                "kind": "ConfigMap",
+                "metadata": { "name": "app-config" },
+                "data": { "HOST": "example.com" },
+            }),
+        );
+        let client = api.client(&runner);
+        let container = container(json!({
+            "name": "main",
+            "envFrom": [
+                { "configMapRef": { "name": "app-config" }, "prefix": "APP_" },
+                { "secretRef": { "name": "optional-secrets", "optional": true } },
+                { "secretRef": { "name": "required-secrets" } },
+            ],
+            "env": [
+                {
+                    "name": "TOKEN",
+                    "valueFrom": {
+                        "secretKeyRef": { "name": "optional-secrets", "key": "token", "optional": true },
+                    },
+                },
+            ],
+        }));
+
+        let env = Resolver::new(&client).resolve(&k8s::Pod::default(), container);
+
+        let names: Vec<&str> = env.vars.iter().map(|var| var.name.as_str()).collect();
+        assert_eq!(names, ["APP_HOST", "TOKEN"]);
+        assert!(env.vars[1].value.segments().is_none());
+        let errors: Vec<String> = env.errors.iter().map(|error| error.to_string()).collect();
+        assert_eq!(
+            errors,
+            [r#"Couldn't fetch secret/required-secrets: secrets "required-secrets" not found"#]
+        );
+    }
+}
@@ -0,0 +1,19 @@
+use std::io::{BufRead, IsTerminal, Write};
+
+/// Asks a yes/no question on the terminal. Refuses outright when stdin isn't
+/// a terminal, so confirmation can't be satisfied by a pipe by accident.
+pub fn confirm(question: &str) -> anyhow::Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow::Error::msg(format!(
+            "{} (confirmation required, but stdin isn't a terminal)",
+            question
+        )));
+    }
+
+    eprint!("{} [y/N] ", question);
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "Yes"))
+}
@@ -0,0 +1,267 @@
+use super::exit::{ChildExit, LaunchError};
+use anyhow::Context;
+use std::process::{Command, ExitStatus, Output};
+
+/// Runs external programs such as `aws` and `kubectl`. Everything that
+/// shells out goes through a runner so it can be replaced in tests, and
+/// changes flightctl makes itself, to files or through the Kubernetes API,
+/// go through it so dry runs can skip them.
+pub trait Runner {
+    /// Runs the program to completion, capturing stdout and stderr.
+    fn output(&self, program: &str, args: &[String]) -> anyhow::Result<Output>;
+
+    /// Runs the program attached to the terminal and waits for it to exit.
+    fn status(&self, program: &str, args: &[String]) -> anyhow::Result<ExitStatus>;
+
+    /// Runs a program that changes something, such as the kubeconfig, an
+    /// AWS profile or a cluster resource, attached to the terminal.
+    fn change(&self, program: &str, args: &[String]) -> anyhow::Result<ExitStatus> {
+        self.status(program, args)
+    }
+
+    /// Hands the terminal to a program the user asked for, such as a console,
+    /// a passthrough command or a login. Returns `None` if it didn't run.
+    fn interactive(&self, program: &str, args: &[String]) -> anyhow::Result<Option<ExitStatus>> {
+        self.status(program, args).map(Some)
+    }
+
+    /// Notes a request flightctl makes to the Kubernetes API itself, such as
+    /// listing pods, described by its verb, path and selectors.
+    fn api_request(&self, description: &str) {
+        log::debug!("{}", description);
+    }
+
+    /// Changes something without running a program, such as merging entries
+    /// into the kubeconfig or patching a resource through the Kubernetes API.
+    /// The description says what would change, and must not include secret
+    /// values since it's logged.
+    fn change_in_process(
+        &self,
+        description: &str,
+        change: &mut dyn FnMut() -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        log::debug!("{}", description);
+        change()
+    }
+}
+
+/// Runs programs for real.
+pub struct SystemRunner;
+
+impl Runner for SystemRunner {
+    fn output(&self, program: &str, args: &[String]) -> anyhow::Result<Output> {
+        log::debug!("Running {} with {:?}", program, args);
+        let output = Command::new(program)
+            .args(args)
+            .output()
+            .with_context(|| launch_error(program))?;
+        log::debug!("{} exited with {}", program, output.status);
+        Ok(output)
+    }
+
+    fn status(&self, program: &str, args: &[String]) -> anyhow::Result<ExitStatus> {
+        log::debug!("Running {} with {:?}", program, args);
+        let status = Command::new(program)
+            .args(args)
+            .status()
+            .with_context(|| launch_error(program))?;
+        log::debug!("{} exited with {}", program, status);
+        Ok(status)
+    }
+}
+
+/// Prints every command and API request as it runs, and prints changes and
+/// interactive commands instead of running them, so they can be reviewed or
+/// copied into a shell.
+pub struct DryRun<'r> {
+    runner: &'r dyn Runner,
+}
+
+impl<'r> DryRun<'r> {
+    pub fn new(runner: &'r dyn Runner) -> DryRun<'r> {
+        DryRun { runner }
+    }
+}
+
+impl<'r> Runner for DryRun<'r> {
+    fn output(&self, program: &str, args: &[String]) -> anyhow::Result<Output> {
+        eprintln!("{}", command_line(program, args));
+        self.runner.output(program, args)
+    }
+
+    fn status(&self, program: &str, args: &[String]) -> anyhow::Result<ExitStatus> {
+        eprintln!("{}", command_line(program, args));
+        self.runner.status(program, args)
+    }
+
+    fn change(&self, program: &str, args: &[String]) -> anyhow::Result<ExitStatus> {
+        eprintln!("{}  # skipped (dry run)", command_line(program, args));
+        Ok(ExitStatus::default())
+    }
+
+    fn interactive(&self, program: &str, args: &[String]) -> anyhow::Result<Option<ExitStatus>> {
+        eprintln!("{}  # would run (dry run)", command_line(program, args));
+        Ok(None)
+    }
+
+    fn api_request(&self, description: &str) {
+        eprintln!("# {}", description);
+    }
+
+    fn change_in_process(
+        &self,
+        description: &str,
+        _change: &mut dyn FnMut() -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        eprintln!("# {}  # skipped (dry run)", description);
+        Ok(())
+    }
+}
+
+/// Runs a program for its output. A failure is reported along with what
+/// the program wrote to stderr, as a general error rather than a forwarded
+/// exit status.
+pub fn run_get_output<S: AsRef<str>>(
+    runner: &dyn Runner,
+    program: &str,
+    args: &[S],
+) -> anyhow::Result<Output> {
+    let args = to_strings(args);
+    let output = runner.output(program, &args)?;
+    match verify_exit(program, args, output.status) {
+        Ok(_) => Ok(output),
+        Err(err) => Err(anyhow::Error::msg(err.to_string()).context(
+            String::from_utf8(output.stderr).unwrap_or_else(|_| String::from("(binary)")),
+        )),
+    }
+}
+
+/// Runs a program attached to the terminal, forwarding its exit status if
+/// it fails.
+pub fn run_print<S: AsRef<str>>(
+    runner: &dyn Runner,
+    program: &str,
+    args: &[S],
+) -> anyhow::Result<()> {
+    let args = to_strings(args);
+    let status = runner.status(program, &args)?;
+    verify_exit(program, args, status)?;
+    Ok(())
+}
+
+/// Runs a program that changes something, forwarding its exit status if it
+/// fails.
+pub fn run_change<S: AsRef<str>>(
+    runner: &dyn Runner,
+    program: &str,
+    args: &[S],
+) -> anyhow::Result<()> {
+    let args = to_strings(args);
+    let status = runner.change(program, &args)?;
+    verify_exit(program, args, status)?;
+    Ok(())
+}
+
+/// Hands the terminal to a program, forwarding its exit status if it fails.
+/// Returns whether it ran, since dry runs only print it.
+pub fn run_interactive<S: AsRef<str>>(
+    runner: &dyn Runner,
+    program: &str,
+    args: &[S],
+) -> anyhow::Result<bool> {
+    let args = to_strings(args);
+    match runner.interactive(program, &args)? {
+        Some(status) => {
+            verify_exit(program, args, status)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// The command as it would be typed into a POSIX shell.
+pub fn command_line(program: &str, args: &[String]) -> String {
+    std::iter::once(program)
+        .chain(args.iter().map(String::as_str))
+        .map(quote)
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn quote(word: &str) -> String {
+    let safe = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,@%+".contains(c));
+    if safe {
+        String::from(word)
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+fn to_strings<S: AsRef<str>>(args: &[S]) -> Vec<String> {
+    args.iter().map(|arg| String::from(arg.as_ref())).collect()
+}
+
+fn launch_error(program: &str) -> LaunchError {
+    LaunchError {
+        program: String::from(program),
+    }
+}
+
+fn verify_exit(program: &str, args: Vec<String>, status: ExitStatus) -> Result<(), ChildExit> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(ChildExit {
+            program: String::from(program),
+            args,
+            status,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flightctl::testing::FakeRunner;
+
+    #[test]
+    fn dry_run_performs_reads_and_skips_changes_and_interactive_commands() {
+        let fake = FakeRunner::new()
+            .expect("aws", &["configure", "list-profiles"])
+            .stdout("sso\n")
+            .expect("kubectl", &["get", "deploy"]);
+        let runner = DryRun::new(&fake);
+
+        let output = run_get_output(&runner, "aws", &["configure", "list-profiles"]).unwrap();
+        run_print(&runner, "kubectl", &["get", "deploy"]).unwrap();
+        run_change(&runner, "kubectl", &["config", "set-context", "staging"]).unwrap();
+        let ran = run_interactive(&runner, "kubectl", &["exec", "web-1", "--", "bash"]).unwrap();
+        runner
+            .change_in_process("Write context staging", &mut || panic!("changed a file"))
+            .unwrap();
+
+        assert_eq!(output.stdout, b"sso\n");
+        assert!(!ran);
+        assert_eq!(fake.calls().len(), 2);
+    }
+
+    #[test]
+    fn quotes_command_lines_for_the_shell() {
+        let args = [
+            "--query",
+            "cluster.{endpoint:endpoint}",
+            "it's",
+            "",
+            "https://example.com/a,b",
+        ]
+        .map(String::from);
+
+        assert_eq!(
+            command_line("aws", &args),
+            r#"aws --query 'cluster.{endpoint:endpoint}' 'it'\''s' '' https://example.com/a,b"#
+        );
+    }
+}
@@ -0,0 +1,330 @@
+//! Fakes for exercising commands without `aws`, `kubectl` or a cluster.
+
+use super::kubeclient::KubeClient;
+use super::runner::Runner;
+use super::Config;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::process::{ExitStatus, Output};
+use std::sync::{Arc, Mutex};
+
+/// Matches any single argument, such as the path of a temporary file.
+pub const ANY: &str = "*";
+
+/// A runner that expects an exact sequence of commands and answers each one
+/// with canned output. Panics on any unexpected command, and on drop if any
+/// expected command never ran.
+pub struct FakeRunner {
+    expected: RefCell<VecDeque<Expectation>>,
+    calls: RefCell<Vec<Vec<String>>>,
+    api_requests: RefCell<Vec<String>>,
+}
+
+struct Expectation {
+    program: String,
+    args: Vec<String>,
+    stdout: String,
+    stderr: String,
+    code: i32,
+}
+
+impl FakeRunner {
+    pub fn new() -> FakeRunner {
+        FakeRunner {
+            expected: RefCell::new(VecDeque::new()),
+            calls: RefCell::new(Vec::new()),
+            api_requests: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Expects the program to run next with exactly these arguments. It
+    /// succeeds with no output unless changed with the methods below.
+    pub fn expect(self, program: &str, args: &[&str]) -> FakeRunner {
+        self.expected.borrow_mut().push_back(Expectation {
+            program: String::from(program),
+            args: args.iter().map(|arg| String::from(*arg)).collect(),
+            stdout: String::new(),
+            stderr: String::new(),
+            code: 0,
+        });
+        self
+    }
+
+    /// Sets what the last expected command writes to stdout.
+    pub fn stdout(self, stdout: &str) -> FakeRunner {
+        self.last(|expectation| expectation.stdout = String::from(stdout))
+    }
+
+    /// Makes the last expected command fail with the status and stderr.
+    pub fn fails(self, code: i32, stderr: &str) -> FakeRunner {
+        self.last(|expectation| {
+            expectation.code = code;
+            expectation.stderr = String::from(stderr);
+        })
+    }
+
+    /// Every command run so far, program first.
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        self.calls.borrow().clone()
+    }
+
+    /// Every Kubernetes API request noted so far.
+    pub fn api_requests(&self) -> Vec<String> {
+        self.api_requests.borrow().clone()
+    }
+
+    fn last(self, f: impl FnOnce(&mut Expectation)) -> FakeRunner {
+        f(self
+            .expected
+            .borrow_mut()
+            .back_mut()
+            .expect("no command expected yet"));
+        self
+    }
+
+    fn next(&self, program: &str, args: &[String]) -> Expectation {
+        let mut call = vec![String::from(program)];
+        call.extend(args.iter().cloned());
+        self.calls.borrow_mut().push(call.clone());
+
+        let expectation = self
+            .expected
+            .borrow_mut()
+            .pop_front()
+            .unwrap_or_else(|| panic!("unexpected command: {:?}", call));
+        let mut expected = vec![expectation.program.clone()];
+        expected.extend(expectation.args.iter().cloned());
+        let matches = expected.len() == call.len()
+            && expected
+                .iter()
+                .zip(&call)
+                .all(|(expected, actual)| expected == ANY || expected == actual);
+        assert!(
+            matches,
+            "unexpected command:\n  expected: {:?}\n    actual: {:?}",
+            expected, call
+        );
+        expectation
+    }
+}
+
+impl Runner for FakeRunner {
+    fn output(&self, program: &str, args: &[String]) -> anyhow::Result<Output> {
+        let expectation = self.next(program, args);
+        Ok(Output {
+            status: exit_status(expectation.code),
+            stdout: expectation.stdout.into_bytes(),
+            stderr: expectation.stderr.into_bytes(),
+        })
+    }
+
+    fn status(&self, program: &str, args: &[String]) -> anyhow::Result<ExitStatus> {
+        Ok(exit_status(self.next(program, args).code))
+    }
+
+    fn api_request(&self, description: &str) {
+        self.api_requests
+            .borrow_mut()
+            .push(String::from(description));
+    }
+}
+
+impl Drop for FakeRunner {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            let remaining: Vec<String> = self
+                .expected
+                .borrow()
+                .iter()
+                .map(|expectation| format!("{} {:?}", expectation.program, expectation.args))
+                .collect();
+            assert!(
+                remaining.is_empty(),
+                "expected commands never ran: {:?}",
+                remaining
+            );
+        }
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw(code << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
+
+/// A Kubernetes API that serves canned resources by path and records every
+/// request it receives. Patches are answered with the resource unchanged.
+#[derive(Clone, Default)]
+pub struct FakeApi {
+    resources: Arc<Mutex<HashMap<String, serde_json::Value>>>,
+    requests: Arc<Mutex<Vec<String>>>,
+    patches: Arc<Mutex<Vec<(String, serde_json::Value)>>>,
+}
+
+impl FakeApi {
+    pub fn new() -> FakeApi {
+        FakeApi::default()
+    }
+
+    /// Serves the body for requests to the path, such as
+    /// `/api/v1/namespaces/default/configmaps/app-config`. Other paths
+    /// respond as not found.
+    pub fn serve(self, path: &str, body: serde_json::Value) -> FakeApi {
+        self.resources
+            .lock()
+            .unwrap()
+            .insert(String::from(path), body);
+        self
+    }
+
+    /// Every request so far, as the path with its query string.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Every patch so far, as the path and the patch it was sent.
+    pub fn patches(&self) -> Vec<(String, serde_json::Value)> {
+        self.patches.lock().unwrap().clone()
+    }
+
+    /// A client for the `default` namespace that talks to this API and runs
+    /// everything else through the runner.
+    pub fn client<'r>(&self, runner: &'r dyn Runner) -> KubeClient<'r> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let api = self.clone();
+        let service = tower::service_fn(move |request: http::Request<hyper::Body>| {
+            let api = api.clone();
+            async move {
+                let (parts, body) = request.into_parts();
+                if parts.method == http::Method::PATCH {
+                    let body = hyper::body::to_bytes(body).await.unwrap();
+                    api.patches.lock().unwrap().push((
+                        String::from(parts.uri.path()),
+                        serde_json::from_slice(&body).unwrap(),
+                    ));
+                }
+                Ok::<_, Infallible>(api.respond(&parts.uri))
+            }
+        });
+        // The client spawns a worker, so it has to be created in the runtime.
+        let client = {
+            let _guard = runtime.enter();
+            kube::Client::new(service, "default")
+        };
+        KubeClient::with_api(runner, "test", runtime, client)
+    }
+
+    fn respond(&self, uri: &http::Uri) -> http::Response<hyper::Body> {
+        self.requests.lock().unwrap().push(uri.to_string());
+        let (status, body) = match self.resources.lock().unwrap().get(uri.path()) {
+            Some(body) => (200, body.clone()),
+            None => (404, not_found(uri.path())),
+        };
+        http::Response::builder()
+            .status(status)
+            .header("content-type", "application/json")
+            .body(hyper::Body::from(body.to_string()))
+            .unwrap()
+    }
+}
+
+fn not_found(path: &str) -> serde_json::Value {
+    let mut segments = path.rsplit('/');
+    let name = segments.next().unwrap_or_default();
+    let plural = segments.next().unwrap_or_default();
+    serde_json::json!({
+        "kind": "Status",
+        "apiVersion": "v1",
+        "metadata": {},
+        "status": "Failure",
+        "message": format!("{} \"{}\" not found", plural, name),
+        "reason": "NotFound",
+        "code": 404,
+    })
+}
+
+/// A workspace with one application released to staging and production,
+/// both on the same EKS cluster.
+pub fn config() -> Config {
+    serde_yaml::from_str(
+        r#"
+apiVersion: flightctl.thoughtbot.com/v1beta1
+kind: Workspace
+releases:
+- name: app-staging
+  application: app
+  context: staging
+  environment: staging
+  manifests:
+    path: envs/staging
+- name: app-production
+  application: app
+  context: production
+  environment: production
+  sensitive: true
+  manifests:
+    path: envs/production
+applications:
+- name: app
+  manifests:
+    provider: kustomize
+    repo: git@example.com:app
+  provider: kubectl
+  params:
+    selector:
+      app: web
+    configMap: app-config
+    secret: app-secrets
+    console:
+      provider: exec
+      params:
+        selector:
+          role: console
+        container: main
+        command: [rails, console]
+contexts:
+- name: staging
+  cluster: cluster
+  namespace: app-staging
+  auth: sso
+- name: production
+  cluster: cluster
+  namespace: app-production
+  auth: sso
+clusters:
+- name: cluster
+  auth: sso
+  provider: eks
+  params:
+    name: example
+    region: us-east-1
+auth:
+- name: sso
+  provider: aws-sso
+  params:
+    sso_region: us-east-1
+    sso_start_url: https://example.awsapps.com/start
+"#,
+    )
+    .unwrap()
+}
+
+/// Finds a release in the fixture by name.
+pub fn release<'a>(config: &'a Config, name: &str) -> &'a super::Release {
+    config
+        .releases
+        .iter()
+        .find(|release| release.name == name)
+        .unwrap()
+}
@@ -0,0 +1,560 @@
+use super::config::{ApplicationConfig, Config, ConfigFile, Console, ManifestConfig};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Section {
+    Applications,
+    Auth,
+    Clusters,
+    Contexts,
+    Releases,
+}
+
+impl Section {
+    fn key(&self) -> &'static str {
+        match self {
+            Section::Applications => "applications",
+            Section::Auth => "auth",
+            Section::Clusters => "clusters",
+            Section::Contexts => "contexts",
+            Section::Releases => "releases",
+        }
+    }
+}
+
+/// Points at an entry in one of the top-level lists, optionally narrowed down
+/// to a (possibly nested) field within that entry.
+#[derive(Debug)]
+pub struct Location {
+    pub section: Section,
+    pub index: usize,
+    pub field: &'static [&'static str],
+}
+
+#[derive(Debug)]
+pub struct Problem {
+    pub location: Location,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Config {
+    /// Checks the whole workspace at once rather than failing on the first
+    /// broken reference a command happens to follow.
+    pub fn validate(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        self.validate_names(&mut problems);
+        self.validate_references(&mut problems);
+        self.validate_releases(&mut problems);
+        self.validate_applications(&mut problems);
+        self.validate_usage(&mut problems);
+        problems.sort_by_key(|problem| (problem.location.section.key(), problem.location.index));
+        problems
+    }
+
+    fn validate_names(&self, problems: &mut Vec<Problem>) {
+        let sections: [(Section, &str, Vec<&str>); 5] = [
+            (
+                Section::Applications,
+                "application",
+                self.applications.iter().map(|a| a.name.as_str()).collect(),
+            ),
+            (
+                Section::Auth,
+                "auth",
+                self.auth.iter().map(|a| a.name.as_str()).collect(),
+            ),
+            (
+                Section::Clusters,
+                "cluster",
+                self.clusters.iter().map(|c| c.name.as_str()).collect(),
+            ),
+            (
+                Section::Contexts,
+                "context",
+                self.contexts.iter().map(|c| c.name.as_str()).collect(),
+            ),
+            (
+                Section::Releases,
+                "release",
+                self.releases.iter().map(|r| r.name.as_str()).collect(),
+            ),
+        ];
+
+        for (section, kind, names) in sections {
+            let mut seen = HashSet::new();
+            for (index, name) in names.into_iter().enumerate() {
+                if !seen.insert(name) {
+                    problems.push(Problem {
+                        location: Location {
+                            section,
+                            index,
+                            field: &["name"],
+                        },
+                        message: format!("Duplicate {} name: {}", kind, name),
+                    });
+                }
+            }
+        }
+    }
+
+    fn validate_references(&self, problems: &mut Vec<Problem>) {
+        for (index, release) in self.releases.iter().enumerate() {
+            if let Err(err) = self.find_application(release) {
+                problems.push(problem(Section::Releases, index, &["application"], err));
+            }
+            if let Err(err) = self.find_context(release) {
+                problems.push(problem(Section::Releases, index, &["context"], err));
+            }
+        }
+
+        for (index, context) in self.contexts.iter().enumerate() {
+            if let Err(err) = self.find_auth(context) {
+                problems.push(problem(Section::Contexts, index, &["auth"], err));
+            }
+            if let Err(err) = self.find_cluster(context) {
+                problems.push(problem(Section::Contexts, index, &["cluster"], err));
+            }
+        }
+
+        for (index, cluster) in self.clusters.iter().enumerate() {
+            if !self.auth.iter().any(|auth| auth.name == cluster.auth) {
+                problems.push(Problem {
+                    location: Location {
+                        section: Section::Clusters,
+                        index,
+                        field: &["auth"],
+                    },
+                    message: format!(
+                        "Cluster {} uses auth {}, which isn't defined",
+                        cluster.name, cluster.auth
+                    ),
+                });
+            }
+        }
+    }
+
+    fn validate_releases(&self, problems: &mut Vec<Problem>) {
+        let mut targets: HashMap<(&str, &str), &str> = HashMap::new();
+
+        for (index, release) in self.releases.iter().enumerate() {
+            if let Some(other) =
+                targets.insert((&release.application, &release.environment), &release.name)
+            {
+                problems.push(Problem {
+                    location: Location {
+                        section: Section::Releases,
+                        index,
+                        field: &["environment"],
+                    },
+                    message: format!(
+                        "Releases {} and {} both deploy application {} to environment {}",
+                        other, release.name, release.application, release.environment
+                    ),
+                });
+            }
+
+            if let Ok(context) = self.find_context(release) {
+                if let Ok(cluster) = self.find_cluster(context) {
+                    if context.auth != cluster.auth {
+                        problems.push(Problem {
+                            location: Location {
+                                section: Section::Releases,
+                                index,
+                                field: &["context"],
+                            },
+                            message: format!(
+                                "Release {} uses context {} with auth {}, but cluster {} uses auth {}",
+                                release.name, context.name, context.auth, cluster.name, cluster.auth
+                            ),
+                        });
+                    }
+                }
+            }
+
+            match &release.manifests {
+                ManifestConfig::Kustomize { path } => {
+                    if path.trim().is_empty() {
+                        problems.push(Problem {
+                            location: Location {
+                                section: Section::Releases,
+                                index,
+                                field: &["manifests", "path"],
+                            },
+                            message: format!(
+                                "Release {} has an empty manifests path",
+                                release.name
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    fn validate_applications(&self, problems: &mut Vec<Problem>) {
+        for (index, application) in self.applications.iter().enumerate() {
+            if application.manifests.repo.trim().is_empty() {
+                problems.push(Problem {
+                    location: Location {
+                        section: Section::Applications,
+                        index,
+                        field: &["manifests", "repo"],
+                    },
+                    message: format!(
+                        "Application {} has an empty manifests repo",
+                        application.name
+                    ),
+                });
+            }
+
+            match &application.config {
+                ApplicationConfig::Kubectl {
+                    console, selector, ..
+                } => {
+                    if selector.is_empty() {
+                        problems.push(Problem {
+                            location: Location {
+                                section: Section::Applications,
+                                index,
+                                field: &["params", "selector"],
+                            },
+                            message: format!(
+                                "Application {} has an empty selector, which matches every workload",
+                                application.name
+                            ),
+                        });
+                    }
+
+                    match console {
+                        Some(Console::Exec { selector, .. }) if selector.is_empty() => {
+                            problems.push(Problem {
+                                location: Location {
+                                    section: Section::Applications,
+                                    index,
+                                    field: &["params", "console", "params", "selector"],
+                                },
+                                message: format!(
+                                    "Application {} has an empty console selector",
+                                    application.name
+                                ),
+                            });
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }
+    }
+
+    fn validate_usage(&self, problems: &mut Vec<Problem>) {
+        for (index, application) in self.applications.iter().enumerate() {
+            if !self
+                .releases
+                .iter()
+                .any(|release| release.application == application.name)
+            {
+                problems.push(unused(
+                    Section::Applications,
+                    index,
+                    "Application",
+                    &application.name,
+                ));
+            }
+        }
+
+        for (index, auth) in self.auth.iter().enumerate() {
+            if !self
+                .contexts
+                .iter()
+                .any(|context| context.auth == auth.name)
+                && !self
+                    .clusters
+                    .iter()
+                    .any(|cluster| cluster.auth == auth.name)
+            {
+                problems.push(unused(Section::Auth, index, "Auth", &auth.name));
+            }
+        }
+
+        for (index, cluster) in self.clusters.iter().enumerate() {
+            if !self
+                .contexts
+                .iter()
+                .any(|context| context.cluster == cluster.name)
+            {
+                problems.push(unused(Section::Clusters, index, "Cluster", &cluster.name));
+            }
+        }
+
+        for (index, context) in self.contexts.iter().enumerate() {
+            if !self
+                .releases
+                .iter()
+                .any(|release| release.context == context.name)
+            {
+                problems.push(unused(Section::Contexts, index, "Context", &context.name));
+            }
+        }
+    }
+}
+
+fn problem(
+    section: Section,
+    index: usize,
+    field: &'static [&'static str],
+    err: anyhow::Error,
+) -> Problem {
+    Problem {
+        location: Location {
+            section,
+            index,
+            field,
+        },
+        message: err.to_string(),
+    }
+}
+
+fn unused(section: Section, index: usize, kind: &str, name: &str) -> Problem {
+    Problem {
+        location: Location {
+            section,
+            index,
+            field: &["name"],
+        },
+        message: format!("{} {} isn't used by anything", kind, name),
+    }
+}
+
+impl ConfigFile {
+    /// Finds the line a problem refers to by scanning the source for the
+    /// entry's list item and then for each key along the field path. This
+    /// only understands block-style YAML, which is what the template uses;
+    /// anything else falls back to no line number.
+    pub fn line_number(&self, location: &Location) -> Option<usize> {
+        let lines: Vec<&str> = self.source.lines().collect();
+        let (start, end) = find_entry(&lines, location.section.key(), location.index)?;
+        let mut line = start;
+        let mut indent = indentation(lines[start]);
+        let mut block = start..end;
+
+        for key in location.field {
+            let prefix = format!("{}:", key);
+            let found = block.clone().find(|&candidate| {
+                let text = lines[candidate];
+                let content = text.trim_start().trim_start_matches("- ");
+                (candidate == start || indentation(text) > indent) && content.starts_with(&prefix)
+            });
+            match found {
+                Some(found) => {
+                    line = found;
+                    indent = indentation(lines[found]);
+                    // The key's block ends where a line is no more indented
+                    // than the key itself.
+                    let block_end = (found + 1..end)
+                        .find(|&next| {
+                            let content = lines[next].trim_start();
+                            !content.is_empty()
+                                && !content.starts_with('#')
+                                && indentation(lines[next]) <= indent
+                        })
+                        .unwrap_or(end);
+                    block = found + 1..block_end;
+                }
+                None => break,
+            }
+        }
+
+        Some(line + 1)
+    }
+}
+
+fn find_entry(lines: &[&str], section: &str, index: usize) -> Option<(usize, usize)> {
+    let header = format!("{}:", section);
+    let section_start = lines.iter().position(|line| line.trim_end() == header)?;
+    let section_end = (section_start + 1..lines.len())
+        .find(|&line| is_top_level_key(lines[line]))
+        .unwrap_or(lines.len());
+
+    let mut item_indent = None;
+    let mut items = Vec::new();
+    for (line, text) in lines
+        .iter()
+        .enumerate()
+        .take(section_end)
+        .skip(section_start + 1)
+    {
+        if text.trim_start().starts_with("- ") || text.trim() == "-" {
+            let indent = indentation(text);
+            if *item_indent.get_or_insert(indent) == indent {
+                items.push(line);
+            }
+        }
+    }
+
+    let start = *items.get(index)?;
+    let end = items.get(index + 1).copied().unwrap_or(section_end);
+    Some((start, end))
+}
+
+fn is_top_level_key(line: &str) -> bool {
+    !line.is_empty()
+        && indentation(line) == 0
+        && !line.starts_with('#')
+        && !line.starts_with('-')
+        && line.contains(':')
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const SOURCE: &str = r#"apiVersion: flightctl.thoughtbot.com/v1beta1
+kind: Workspace
+releases:
+- name: app-staging
+  application: app
+  context: staging
+  environment: staging
+  manifests:
+    path: envs/staging
+- name: app-staging
+  application: missing
+  context: staging
+  environment: staging
+  manifests:
+    path: ""
+applications:
+- name: app
+  manifests:
+    provider: kustomize
+    repo: git@example.com:app
+  provider: kubectl
+  params:
+    selector: {}
+contexts:
+- name: staging
+  cluster: cluster
+  namespace: app-staging
+  auth: sso
+clusters:
+- name: cluster
+  auth: sso
+  provider: eks
+  params:
+    name: example
+    region: us-east-1
+auth:
+- name: sso
+  provider: aws-sso
+  params:
+    sso_region: us-east-1
+    sso_start_url: https://example.awsapps.com/start
+"#;
+
+    fn config_file() -> ConfigFile {
+        ConfigFile {
+            config: serde_yaml::from_str(SOURCE).unwrap(),
+            path: PathBuf::from("flightctl.yaml"),
+            source: String::from(SOURCE),
+        }
+    }
+
+    #[test]
+    fn reports_problems_with_their_lines() {
+        let file = config_file();
+
+        let problems: Vec<(Option<usize>, String)> = file
+            .config
+            .validate()
+            .iter()
+            .map(|problem| (file.line_number(&problem.location), problem.to_string()))
+            .collect();
+
+        assert_eq!(
+            problems,
+            [
+                (
+                    Some(23),
+                    String::from(
+                        "Application app has an empty selector, which matches every workload"
+                    )
+                ),
+                (
+                    Some(10),
+                    String::from("Duplicate release name: app-staging")
+                ),
+                (
+                    Some(11),
+                    String::from(
+                        "Release app-staging uses application missing, which isn't defined"
+                    )
+                ),
+                (
+                    Some(15),
+                    String::from("Release app-staging has an empty manifests path")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_entry_or_nothing() {
+        let file = config_file();
+        let location = |section, index, field| Location {
+            section,
+            index,
+            field,
+        };
+
+        assert_eq!(
+            file.line_number(&location(Section::Contexts, 0, &["namespace"])),
+            Some(27)
+        );
+        assert_eq!(
+            file.line_number(&location(Section::Clusters, 0, &["params", "missing"])),
+            Some(33)
+        );
+        assert_eq!(
+            file.line_number(&location(Section::Releases, 2, &["name"])),
+            None
+        );
+    }
+
+    #[test]
+    fn looks_for_nested_keys_only_under_their_parent() {
+        let file = ConfigFile {
+            source: String::from(
+                r#"applications:
+- name: app
+  params:
+    configMap: app-config
+  manifests:
+    selector:
+      app: web
+"#,
+            ),
+            ..config_file()
+        };
+
+        assert_eq!(
+            file.line_number(&Location {
+                section: Section::Applications,
+                index: 0,
+                field: &["params", "selector"],
+            }),
+            Some(3)
+        );
+    }
+}
@@ -1,4 +1,9 @@
+use anyhow::Context;
 use env_logger;
+use flightctl::cluster_cache::ClusterCache;
+use flightctl::exit::{self, Failure};
+use flightctl::kubeclient::ExecOptions;
+use flightctl::runner::{DryRun, Runner, SystemRunner};
 use flightctl::{Config, ConfigFile, Release, Selector};
 use log;
 use structopt::StructOpt;
@@ -12,6 +17,15 @@ struct Opt {
     #[structopt(short, long)]
     debug: bool,
 
+    /// Print the aws and kubectl commands being run, skipping any that
+    /// would change something
+    #[structopt(long, alias = "explain")]
+    dry_run: bool,
+
+    /// Fetch cluster details from AWS rather than using cached ones
+    #[structopt(long)]
+    refresh: bool,
+
     #[structopt(subcommand)]
     cmd: Option<Command>,
 
@@ -30,6 +44,15 @@ enum Command {
 
     /// Fetch configuration variables for a release
     Config {
+        #[structopt(subcommand)]
+        cmd: Option<ConfigCommand>,
+
+        #[structopt(flatten)]
+        options: commands::config::Options,
+
+        #[structopt(flatten)]
+        target: commands::config::Target,
+
         #[structopt(flatten)]
         selector: Selector,
     },
@@ -40,6 +63,12 @@ enum Command {
         selector: Selector,
     },
 
+    /// Manage the Kubernetes contexts for this workspace
+    Context {
+        #[structopt(subcommand)]
+        cmd: ContextCommand,
+    },
+
     /// Run a kubectl command for a release
     Kubectl {
         cmd: Vec<String>,
@@ -47,6 +76,25 @@ enum Command {
         selector: Selector,
     },
 
+    /// Remove the AWS profile and Kubernetes context set up for a release
+    #[structopt(alias = "clean")]
+    Logout {
+        /// Remove them for every release in the workspace
+        #[structopt(long)]
+        all: bool,
+
+        /// Also sign out of every AWS SSO session on this machine
+        #[structopt(long)]
+        sso_logout: bool,
+
+        /// Remove them without asking for confirmation
+        #[structopt(short, long)]
+        yes: bool,
+
+        #[structopt(flatten)]
+        selector: Selector,
+    },
+
     /// List processes running for a release
     Ps {
         #[structopt(flatten)]
@@ -57,10 +105,21 @@ enum Command {
     Run {
         cmd: Vec<String>,
 
+        /// Don't allocate a TTY, even when running in a terminal
+        #[structopt(long)]
+        no_tty: bool,
+
+        /// Don't pass stdin through to the command
+        #[structopt(long)]
+        no_stdin: bool,
+
         #[structopt(flatten)]
         selector: Selector,
     },
 
+    /// Check this workspace's configuration for problems
+    Validate,
+
     /// View information about this workspace
     View {
         #[structopt(subcommand)]
@@ -68,6 +127,113 @@ enum Command {
     },
 }
 
+#[derive(Debug, StructOpt)]
+enum ConfigCommand {
+    /// Compare configuration variables between two releases
+    Diff {
+        #[structopt(short, long)]
+        application: Option<String>,
+
+        /// Environments to compare (given twice)
+        #[structopt(short, long = "environment", number_of_values = 1)]
+        environments: Vec<String>,
+
+        #[structopt(flatten)]
+        target: commands::config::Target,
+    },
+
+    /// Edit configuration variables in $EDITOR
+    Edit {
+        #[structopt(flatten)]
+        options: commands::config::set::Options,
+
+        #[structopt(flatten)]
+        target: commands::config::Target,
+
+        #[structopt(flatten)]
+        selector: Selector,
+    },
+
+    /// Print the value of a single configuration variable
+    Get {
+        key: String,
+
+        /// Print the value even if it comes from a secret
+        #[structopt(long)]
+        reveal: bool,
+
+        #[structopt(flatten)]
+        target: commands::config::Target,
+
+        #[structopt(flatten)]
+        selector: Selector,
+    },
+
+    /// Set configuration variables in the ConfigMap or Secret they come from
+    Set {
+        /// Variables to set, as KEY=VALUE
+        #[structopt(required = true, parse(try_from_str = commands::config::set::parse_assignment))]
+        assignments: Vec<(String, String)>,
+
+        /// Store new variables in the application's secret rather than its ConfigMap
+        #[structopt(long)]
+        secret: bool,
+
+        #[structopt(flatten)]
+        options: commands::config::set::Options,
+
+        #[structopt(flatten)]
+        target: commands::config::Target,
+
+        #[structopt(flatten)]
+        selector: Selector,
+    },
+
+    /// Remove configuration variables from the ConfigMap or Secret they come from
+    Unset {
+        #[structopt(required = true)]
+        keys: Vec<String>,
+
+        #[structopt(flatten)]
+        options: commands::config::set::Options,
+
+        #[structopt(flatten)]
+        target: commands::config::Target,
+
+        #[structopt(flatten)]
+        selector: Selector,
+    },
+}
+
+impl ConfigCommand {
+    fn name(&self) -> &'static str {
+        match self {
+            ConfigCommand::Diff { .. } => "diff",
+            ConfigCommand::Edit { .. } => "edit",
+            ConfigCommand::Get { .. } => "get",
+            ConfigCommand::Set { .. } => "set",
+            ConfigCommand::Unset { .. } => "unset",
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+enum ContextCommand {
+    /// Report and repair differences between a release's Kubernetes context and this workspace
+    Sync {
+        /// Only report differences, exiting with 1 if there are any
+        #[structopt(long)]
+        check: bool,
+
+        /// Sync the context of every release in the workspace
+        #[structopt(long)]
+        all: bool,
+
+        #[structopt(flatten)]
+        selector: Selector,
+    },
+}
+
 #[derive(Debug, StructOpt)]
 enum ViewCommand {
     /// View applications for this workspace
@@ -87,28 +253,51 @@ enum ViewCommand {
 }
 
 fn preflight<'a>(
+    runner: &dyn Runner,
     config: &'a Config,
     opt: &Opt,
     selector: &Selector,
 ) -> anyhow::Result<&'a Release> {
     log::debug!("Beginning preflight");
-    let release = opt.selector.merge(selector).apply(&config)?;
-    flightctl::authorize::run(&config, &release)?;
-    flightctl::context::prepare(&config, &release)?;
+    let release = opt
+        .selector
+        .merge(selector)
+        .apply(&config)
+        .context(Failure::Selection)?;
+    flightctl::authorize::run(runner, &config, &release).context(Failure::Authorization)?;
+    let cache = ClusterCache::new(opt.refresh);
+    flightctl::context::prepare(runner, &cache, &config, &release).context(Failure::Context)?;
     log::debug!("Preflight complete");
     Ok(release)
 }
 
+/// Every release in the workspace, or the selected one.
+fn select_releases<'a>(
+    config: &'a Config,
+    opt: &Opt,
+    selector: &Selector,
+    all: bool,
+) -> anyhow::Result<Vec<&'a Release>> {
+    if all {
+        Ok(config.releases.iter().collect())
+    } else {
+        let release = opt
+            .selector
+            .merge(selector)
+            .apply(config)
+            .context(Failure::Selection)?;
+        Ok(vec![release])
+    }
+}
+
 fn init_logger(default: &str) {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default))
         .format_timestamp(None)
         .init();
 }
 
-fn main() -> anyhow::Result<()> {
+fn main() {
     let opt = Opt::from_args();
-    let config_file = ConfigFile::find()?;
-    let config = config_file.config;
 
     if opt.debug {
         init_logger("debug");
@@ -116,46 +305,233 @@ fn main() -> anyhow::Result<()> {
         init_logger("info");
     }
 
+    let system = SystemRunner;
+    let dry_run = DryRun::new(&system);
+    let runner: &dyn Runner = if opt.dry_run { &dry_run } else { &system };
+
+    if let Err(err) = run(runner, opt) {
+        if exit::should_report(&err) {
+            eprintln!("Error: {:?}", err);
+        } else {
+            log::debug!("{}", err);
+        }
+        std::process::exit(exit::code(&err));
+    }
+}
+
+fn run(runner: &dyn Runner, opt: Opt) -> anyhow::Result<()> {
+    let config_file = ConfigFile::find().context(Failure::Config)?;
+    let config = &config_file.config;
+
     if log::log_enabled!(log::Level::Debug) {
         log::debug!("Loaded configuration from {}:", config_file.path.display());
         log::debug!("{:?}", config);
         log::debug!("Parsed command: {:?}", opt.cmd);
     }
 
+    if let Some(Command::Config {
+        cmd: Some(ref cmd),
+        ref options,
+        ..
+    }) = opt.cmd
+    {
+        options.check_unused(cmd.name())?;
+    }
+
     match opt.cmd {
         Some(Command::Aws {
             ref cmd,
             ref selector,
         }) => {
-            let release = preflight(&config, &opt, &selector)?;
-            commands::aws::run(&config, release, cmd)
+            let release = preflight(runner, config, &opt, &selector)?;
+            commands::aws::run(runner, config, release, cmd)
+        }
+        Some(Command::Config {
+            cmd:
+                Some(ConfigCommand::Diff {
+                    ref application,
+                    ref environments,
+                    target: ref diff_target,
+                }),
+            ref selector,
+            ref target,
+            ..
+        }) => {
+            if environments.len() != 2 {
+                return Err(anyhow::Error::msg(
+                    "Expected exactly two environments to compare",
+                ))
+                .context(Failure::Selection);
+            }
+            let releases = environments
+                .iter()
+                .map(|environment| {
+                    let selector = selector.merge(&Selector {
+                        application: application.clone(),
+                        environment: Some(environment.clone()),
+                    });
+                    preflight(runner, config, &opt, &selector)
+                })
+                .collect::<anyhow::Result<Vec<&Release>>>()?;
+            commands::config::diff::run(
+                runner,
+                config,
+                &target.merge(diff_target),
+                releases[0],
+                releases[1],
+            )
+        }
+        Some(Command::Config {
+            cmd:
+                Some(ConfigCommand::Edit {
+                    ref options,
+                    target: ref edit_target,
+                    selector: ref edit_selector,
+                }),
+            ref selector,
+            ref target,
+            ..
+        }) => {
+            let release = preflight(runner, config, &opt, &selector.merge(edit_selector))?;
+            commands::config::edit::run(
+                runner,
+                config,
+                release,
+                &target.merge(edit_target),
+                options,
+            )
+        }
+        Some(Command::Config {
+            cmd:
+                Some(ConfigCommand::Get {
+                    ref key,
+                    reveal,
+                    target: ref get_target,
+                    selector: ref get_selector,
+                }),
+            ref selector,
+            ref target,
+            ..
+        }) => {
+            let release = preflight(runner, config, &opt, &selector.merge(get_selector))?;
+            commands::config::get::run(
+                runner,
+                config,
+                release,
+                &target.merge(get_target),
+                key,
+                reveal,
+            )
+        }
+        Some(Command::Config {
+            cmd:
+                Some(ConfigCommand::Set {
+                    ref assignments,
+                    secret,
+                    ref options,
+                    target: ref set_target,
+                    selector: ref set_selector,
+                }),
+            ref selector,
+            ref target,
+            ..
+        }) => {
+            let release = preflight(runner, config, &opt, &selector.merge(set_selector))?;
+            commands::config::set::set(
+                runner,
+                config,
+                release,
+                &target.merge(set_target),
+                assignments,
+                secret,
+                options,
+            )
         }
-        Some(Command::Config { ref selector }) => {
-            let release = preflight(&config, &opt, &selector)?;
-            commands::config::print(&config, release)
+        Some(Command::Config {
+            cmd:
+                Some(ConfigCommand::Unset {
+                    ref keys,
+                    ref options,
+                    target: ref unset_target,
+                    selector: ref unset_selector,
+                }),
+            ref selector,
+            ref target,
+            ..
+        }) => {
+            let release = preflight(runner, config, &opt, &selector.merge(unset_selector))?;
+            commands::config::set::unset(
+                runner,
+                config,
+                release,
+                &target.merge(unset_target),
+                keys,
+                options,
+            )
+        }
+        Some(Command::Config {
+            cmd: None,
+            ref options,
+            ref target,
+            ref selector,
+        }) => {
+            let release = preflight(runner, config, &opt, &selector)?;
+            commands::config::print(runner, config, release, target, options)
         }
         Some(Command::Console { ref selector }) => {
-            let release = preflight(&config, &opt, &selector)?;
-            commands::console::run_default(&config, release)
+            let release = preflight(runner, config, &opt, &selector)?;
+            commands::console::run_default(runner, config, release)
+        }
+        Some(Command::Context {
+            cmd:
+                ContextCommand::Sync {
+                    check,
+                    all,
+                    ref selector,
+                },
+        }) => {
+            let releases = select_releases(config, &opt, selector, all)?;
+            let cache = ClusterCache::new(opt.refresh);
+            let cache = if check { cache.read_only() } else { cache };
+            commands::context::sync(runner, &cache, config, &releases, check)
         }
         Some(Command::Kubectl {
             ref cmd,
             ref selector,
         }) => {
-            let release = preflight(&config, &opt, &selector)?;
-            commands::kubectl::run(&config, release, cmd)
+            let release = preflight(runner, config, &opt, &selector)?;
+            commands::kubectl::run(runner, config, release, cmd)
+        }
+        Some(Command::Logout {
+            all,
+            sso_logout,
+            yes,
+            ref selector,
+        }) => {
+            let releases = select_releases(config, &opt, selector, all)?;
+            let cache = ClusterCache::new(opt.refresh).read_only();
+            commands::logout::run(runner, &cache, config, &releases, sso_logout, yes)
         }
         Some(Command::Ps { ref selector }) => {
-            let release = preflight(&config, &opt, &selector)?;
-            commands::process::run(&config, release)
+            let release = preflight(runner, config, &opt, &selector)?;
+            commands::process::run(runner, config, release)
         }
         Some(Command::Run {
             ref cmd,
+            no_tty,
+            no_stdin,
             ref selector,
         }) => {
-            let release = preflight(&config, &opt, &selector)?;
-            commands::console::run_command(&config, release, cmd)
+            let release = preflight(runner, config, &opt, &selector)?;
+            commands::console::run_command(
+                runner,
+                config,
+                release,
+                cmd,
+                &ExecOptions::detect(no_stdin, no_tty),
+            )
         }
+        Some(Command::Validate) => commands::validate::run(&config_file).context(Failure::Config),
         Some(Command::View {
             cmd: ViewCommand::Applications,
         }) => commands::view::applications(config),
@@ -178,3 +554,47 @@ fn main() -> anyhow::Result<()> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flightctl::testing::{self, FakeRunner};
+
+    fn selector(application: &str, environment: &str) -> Selector {
+        Selector {
+            application: Some(String::from(application)),
+            environment: Some(String::from(environment)),
+        }
+    }
+
+    #[test]
+    fn preflight_reports_selection_failure() {
+        let config = testing::config();
+        let opt = Opt::from_iter(["flightctl"]);
+        let runner = FakeRunner::new();
+
+        let err = preflight(&runner, &config, &opt, &selector("app", "review")).unwrap_err();
+
+        assert_eq!(exit::code(&err), Failure::Selection.code());
+        assert!(runner.calls().is_empty());
+    }
+
+    #[test]
+    fn preflight_reports_authorization_failure() {
+        let config = testing::config();
+        let opt = Opt::from_iter(["flightctl"]);
+        let runner = FakeRunner::new()
+            .expect("aws", &["configure", "list-profiles"])
+            .stdout("sso\n")
+            .expect("aws", &["--profile", "sso", "sts", "get-caller-identity"])
+            .fails(255, "The SSO session has expired")
+            .expect("aws", &["--profile", "sso", "sso", "login"])
+            .expect("aws", &["--profile", "sso", "sts", "get-caller-identity"])
+            .fails(255, "Access denied");
+
+        let err = preflight(&runner, &config, &opt, &selector("app", "production")).unwrap_err();
+
+        assert_eq!(exit::code(&err), Failure::Authorization.code());
+        assert!(format!("{:#}", err).contains("Access denied"));
+    }
+}